@@ -0,0 +1,123 @@
+// compares the finalizer's ranking (which merges each word's spellings) and blacklist filtering against the original
+// nested loops
+// run with: cargo bench --bench dedup
+
+// only some of the module is benchmarked
+#[allow(dead_code)]
+#[path = "../src/dedup.rs"]
+mod dedup;
+#[path = "../src/ranking.rs"]
+mod ranking;
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+// the nested-loop dedup and blacklist removal the finalizer used to run
+fn legacy_finish(end_list: &mut Vec<String>, blacklist: &[String]) {
+    for start in 0..end_list.len() {
+        let mut end = start;
+        while end < end_list.len() {
+            if start != end && end_list[start].eq_ignore_ascii_case(end_list[end].as_str()) {
+                end_list.remove(end);
+            } else {
+                end += 1;
+            }
+        }
+    }
+
+    for entry in blacklist {
+        let mut index: usize = 0;
+        while index < end_list.len() {
+            if entry.eq_ignore_ascii_case(end_list[index].as_str()) {
+                end_list.remove(index);
+                break;
+            }
+            index += 1;
+        }
+    }
+}
+
+// what the finalizer runs: ranking the pages' words merges their spellings, then the blacklist is removed
+fn ranked_finish(pages: &[Vec<String>], blacklist: &[String]) -> Vec<String> {
+    let points = ranking::RankingPoints { page: 10.0, occurrence: 1.0 };
+    let weighed = pages.iter().map(|page| page.iter().map(|word| (word.clone(), 1.0)));
+    let mut end_list: Vec<String> = ranking::rank(weighed, &points).into_iter().map(|stats| stats.spelling).collect();
+    let folded: HashSet<String> = blacklist.iter().map(|entry| dedup::fold_case(entry)).collect();
+    dedup::remove_blacklisted(&mut end_list, &folded);
+    end_list
+}
+
+// the ranking reorders the words, so the two are compared by which words they kept
+fn sorted(words: &[String]) -> Vec<String> {
+    let mut words = words.to_vec();
+    words.sort();
+    words
+}
+
+// the words split up into pages, the way the Spider hands them to the finalizer
+fn paginate(words: &[String]) -> Vec<Vec<String>> {
+    words.chunks(250).map(|page| page.to_vec()).collect()
+}
+
+// a page's worth of tokens: a vocabulary a third the size of the list, in random case, so there are lots of duplicates
+// some of the words aren't ASCII, so the non-ASCII letters' cases have to be treated the same way too
+fn generate_words(count: usize) -> Vec<String> {
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut next = move || {
+        state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        state >> 33
+    };
+    let vocabulary = (count / 3).max(1) as u64;
+    (0..count).map(|_| {
+        let index = next() % vocabulary;
+        let word = match index % 4 {
+            0 => format!("wörd{:x}", index),
+            _ => format!("word{:x}", index)
+        };
+        match next() % 3 {
+            0 => word.to_uppercase(),
+            1 => word.to_ascii_uppercase(),  // "WöRD", which only matches "wörd" if the ASCII letters are folded
+            _ => word
+        }
+    }).collect()
+}
+
+fn time<F: FnMut()>(mut run: F) -> Duration {
+    let start = Instant::now();
+    run();
+    start.elapsed()
+}
+
+fn main() {
+    // words whose non-ASCII letters only differ in case aren't duplicates, and neither are the dotted and dotless i's
+    let mut legacy: Vec<String> = ["Ärger", "ärger", "ÄRGER", "äRGER", "Straße", "STRASSE", "straße", "İstanbul", "istanbul", "ıSTANBUL"]
+        .iter().map(|word| word.to_string()).collect();
+    let pages = paginate(&legacy);
+    let blacklist: Vec<String> = vec!["ärger".to_string(), "STRAßE".to_string()];
+    legacy_finish(&mut legacy, &blacklist);
+    let ranked = ranked_finish(&pages, &blacklist);
+    assert_eq!(sorted(&legacy), sorted(&ranked), "both algorithms must treat non-ASCII letters the same way");
+
+    let blacklist: Vec<String> = generate_words(300);
+
+    println!("{:>10} {:>14} {:>14} {:>10}", "words", "nested loops", "ranking", "speedup");
+    for &count in &[1_000, 5_000, 20_000, 200_000] {
+        let words = generate_words(count);
+        let pages = paginate(&words);
+
+        let mut ranked = Vec::new();
+        let ranked_time = time(|| ranked = ranked_finish(&pages, &blacklist));
+
+        // the old algorithm takes minutes on a full-sized list, so it's skipped there
+        if count > 20_000 {
+            println!("{:>10} {:>14} {:>12.2?} {:>10}", count, "(skipped)", ranked_time, "-");
+            continue;
+        }
+        let mut legacy = words.clone();
+        let legacy_time = time(|| legacy_finish(&mut legacy, &blacklist));
+
+        assert_eq!(sorted(&legacy), sorted(&ranked), "both algorithms must keep the same words");
+        println!("{:>10} {:>12.2?} {:>12.2?} {:>9.0}x", count, legacy_time, ranked_time,
+                 legacy_time.as_secs_f64() / ranked_time.as_secs_f64().max(1e-9));
+    }
+}
@@ -0,0 +1,588 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use regex::{Regex, RegexSet, RegexSetBuilder};
+use url::Url;
+
+use config::Value;
+
+use super::configuration;
+use super::dedup;
+use super::engine;
+use super::spider::{PageWords, Spider};
+
+// how each line of a blacklist file is compared to the words
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MatchStyle {
+    Exact,  // the whole word, ignoring case
+    Regex,  // anywhere in the word unless anchored with ^ and $, ignoring case
+    Glob,  // the whole word, with "*", "?" and "[...]" wildcards, ignoring case
+}
+
+// the words from one [blacklists.*] table, and the pages it applies to
+#[derive(Debug)]
+pub struct Blacklist {
+    name: String,
+    exact: HashSet<String>,  // case-folded
+    patterns: Option<RegexSet>,
+    engines: Vec<String>,  // empty for every engine
+    domains: Vec<String>,  // empty for every domain
+}
+
+impl Blacklist {
+    // a scoped blacklist only applies to the words from some pages, so it has to run before they're merged
+    pub fn is_scoped(&self) -> bool {
+        !self.engines.is_empty() || !self.domains.is_empty()
+    }
+
+    // a page is in scope if it came from one of the engines or is on one of the domains (or a subdomain of them)
+    pub fn applies_to(&self, page: &PageWords) -> bool {
+        if !self.is_scoped() {
+            return true;
+        }
+        if let Some(engine) = &page.engine {
+            if self.engines.iter().any(|name| name.eq_ignore_ascii_case(engine)) {
+                return true;
+            }
+        }
+        match Url::parse(page.url.as_str()) {
+            Ok(url) => match url.host_str() {
+                Some(host) => {
+                    let host = host.to_lowercase();
+                    self.domains.iter().any(|domain| {
+                        host == *domain || host.ends_with(format!(".{}", domain).as_str())
+                    })
+                }
+                None => false
+            },
+            Err(_) => false
+        }
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        if self.exact.contains(&dedup::fold_case(word)) {
+            return true;
+        }
+        match &self.patterns {
+            Some(patterns) => patterns.is_match(word),
+            None => false
+        }
+    }
+
+    // removes every blacklisted word, for when the page it came from no longer matters
+    pub fn remove_from(&self, words: &mut Vec<String>) {
+        dedup::remove_blacklisted(words, &self.exact);
+        if let Some(patterns) = &self.patterns {
+            words.retain(|word| !patterns.is_match(word));
+        }
+    }
+}
+
+// drops the words that a scoped blacklist covers from each page it applies to
+pub fn filter_pages(pages: &mut [PageWords], blacklists: &[Blacklist]) {
+    for page in pages.iter_mut() {
+        let applicable: Vec<&Blacklist> = blacklists.iter()
+            .filter(|blacklist| blacklist.is_scoped() && blacklist.applies_to(page))
+            .collect();
+        if applicable.is_empty() {
+            continue;
+        }
+        let before = page.words.len();
+        page.words.retain(|word| !applicable.iter().any(|blacklist| blacklist.contains(word.text.as_str())));
+        if configuration::read_debug() {
+            println!("~~~  Scoped blacklists removed {} words from {}", before - page.words.len(), page.url);
+        }
+    }
+}
+
+// every blacklist in the [blacklists] table, or everything in "./blacklists/" if there isn't one
+pub fn read_blacklists() -> Vec<Blacklist> {
+    let tables = match configuration::CONFIGURATION.read().unwrap().get_table("blacklists") {
+        Ok(tables) => tables,
+        Err(_) => {
+            println!("!!!  Config Table \"blacklists\" is missing. Defaulting to every file in \"./blacklists/\"");
+            let mut blacklist = Blacklist {
+                name: String::from("default"),
+                exact: HashSet::new(),
+                patterns: None,
+                engines: Vec::new(),
+                domains: Vec::new(),
+            };
+            for line in read_lines(Path::new("./blacklists/")) {
+                blacklist.exact.insert(dedup::fold_case(line.as_str()));
+            }
+            return vec![blacklist];
+        }
+    };
+
+    let mut blacklists = Vec::new();
+    for (name, table) in tables {
+        match table.into_table() {
+            Ok(table) => {
+                let blacklist = build_blacklist(name.clone(), &table);
+                if configuration::read_debug() {
+                    println!("~~~  Blacklist \"{}\" has {} words and {} patterns (engines: {:?}, domains: {:?})",
+                             blacklist.name, blacklist.exact.len(),
+                             blacklist.patterns.as_ref().map(|patterns| patterns.len()).unwrap_or(0),
+                             blacklist.engines, blacklist.domains);
+                }
+                blacklists.push(blacklist);
+            }
+            Err(e) => println!("!!!  Blacklist \"{}\" isn't a table and will be ignored: {}", name, e)
+        }
+    }
+    blacklists
+}
+
+fn build_blacklist(name: String, table: &HashMap<String, Value>) -> Blacklist {
+    let style = match table.get("match").map(|value| value.clone().into_str()) {
+        Some(Ok(style)) => match style.to_lowercase().as_str() {
+            "exact" => MatchStyle::Exact,
+            "regex" => MatchStyle::Regex,
+            "glob" => MatchStyle::Glob,
+            other => {
+                println!("!!!  Unknown match style \"{}\" in blacklist \"{}\". Defaulting to \"exact\"", other, name);
+                MatchStyle::Exact
+            }
+        },
+        Some(Err(e)) => {
+            println!("!!!  \"match\" in blacklist \"{}\" isn't a valid string: {}", name, e);
+            MatchStyle::Exact
+        }
+        None => MatchStyle::Exact
+    };
+
+    let mut exact = HashSet::new();
+    let mut expressions = Vec::new();
+    for path in read_string_array(table, "paths", &name) {
+        for line in read_lines(Path::new(path.as_str())) {
+            match style {
+                MatchStyle::Exact => {
+                    exact.insert(dedup::fold_case(line.as_str()));
+                }
+                MatchStyle::Regex => expressions.push(line),
+                MatchStyle::Glob => expressions.push(glob_to_regex(line.as_str())),
+            }
+        }
+    }
+
+    let patterns = build_patterns(&name, &expressions);
+
+    Blacklist {
+        engines: read_string_array(table, "engines", &name),
+        domains: read_string_array(table, "domains", &name).iter().map(|domain| domain.to_lowercase()).collect(),
+        name,
+        exact,
+        patterns,
+    }
+}
+
+// every pattern that compiles, so one bad line doesn't throw away the rest of the blacklist
+fn build_patterns(name: &str, expressions: &[String]) -> Option<RegexSet> {
+    let valid: Vec<&String> = expressions.iter()
+        .filter(|expression| match Regex::new(expression.as_str()) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("!!!  Skipping the invalid pattern \"{}\" in blacklist \"{}\": {}", expression, name, e);
+                false
+            }
+        })
+        .collect();
+    if valid.is_empty() {
+        return None;
+    }
+    match RegexSetBuilder::new(valid).case_insensitive(true).build() {
+        Ok(patterns) => Some(patterns),
+        Err(e) => {
+            println!("!!!  Blacklist \"{}\" has too many patterns to use: {}", name, e);
+            None
+        }
+    }
+}
+
+fn read_string_array(table: &HashMap<String, Value>, key: &str, name: &str) -> Vec<String> {
+    match table.get(key).map(|value| value.clone().into_array()) {
+        Some(Ok(values)) => values.into_iter()
+            .filter_map(|value| match value.into_str() {
+                Ok(string) => Some(string),
+                Err(e) => {
+                    println!("!!!  An entry of \"{}\" in blacklist \"{}\" isn't a valid string: {}", key, name, e);
+                    None
+                }
+            })
+            .collect(),
+        Some(Err(e)) => {
+            println!("!!!  \"{}\" in blacklist \"{}\" should be an array: {}", key, name, e);
+            Vec::new()
+        }
+        None => Vec::new()
+    }
+}
+
+// every non-empty line of a file, or of every file in a directory
+fn read_lines(path: &Path) -> Vec<String> {
+    let files: Vec<PathBuf> = if path.is_dir() {
+        match fs::read_dir(path) {
+            Ok(contents) => contents
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file())
+                .collect(),
+            Err(e) => {
+                println!("!!!  Blacklist directory \"{}\" could not be read: {}", path.display(), e);
+                return Vec::new();
+            }
+        }
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut lines = Vec::new();
+    for file in files {
+        match File::open(&file) {
+            Ok(blacklist_file) => {
+                if configuration::read_debug() {
+                    println!("###  Using blacklist from path: {}", file.display());
+                }
+                for line in BufReader::new(blacklist_file).lines() {
+                    match line {
+                        Ok(line) => {
+                            let line = line.trim_end();
+                            if !line.is_empty() {
+                                lines.push(line.to_string());
+                            }
+                        }
+                        Err(e) => {
+                            println!("!!!  Blacklist file \"{}\" could not be read: {}", file.display(), e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => println!("!!!  Blacklist file \"{}\" could not be opened: {}", file.display(), e)
+        }
+    }
+    lines
+}
+
+// turns a glob like "wiki*" or "?ditor" into an anchored regex
+fn glob_to_regex(glob: &str) -> String {
+    let mut expression = String::from("^");
+    // where the members of the "[...]" class we're in start in the expression
+    let mut class_start: Option<usize> = None;
+    for character in glob.chars() {
+        match (character, class_start) {
+            ('*', None) => expression.push_str(".*"),
+            ('?', None) => expression.push('.'),
+            ('[', None) => {
+                expression.push('[');
+                class_start = Some(expression.len());
+            }
+            // a "!" first negates the class, and a "]" first is one of its members
+            ('!', Some(start)) if expression.len() == start => {
+                expression.push('^');
+                class_start = Some(expression.len());
+            }
+            (']', Some(start)) if expression.len() > start => {
+                expression.push(']');
+                class_start = None;
+            }
+            // ranges ("[a-z]") are kept, but "--" would be a set difference to the regex crate
+            ('-', Some(start)) if expression.len() > start && !expression.ends_with('-') => expression.push('-'),
+            _ => expression.push_str(regex::escape(character.to_string().as_str()).as_str()),
+        }
+    }
+    expression.push('$');
+    // an unclosed "[", or a class the regex crate won't take (e.g. "[z-a]"), is just characters
+    // one invalid pattern would stop the whole blacklist from matching anything
+    if class_start.is_some() || Regex::new(expression.as_str()).is_err() {
+        return format!("^{}$", regex::escape(glob));
+    }
+    expression
+}
+
+// unrelated searches whose results should only have the engine's (and the sites') chrome in common
+const DEFAULT_CONTROL_QUERIES: [&str; 12] = [
+    "banana bread recipe",
+    "volcano eruption history",
+    "medieval castle architecture",
+    "jazz saxophone players",
+    "penguin migration",
+    "quantum computing basics",
+    "origami crane instructions",
+    "desert cactus species",
+    "steam locomotive museum",
+    "chess opening theory",
+    "coral reef bleaching",
+    "violin making",
+];
+
+// runs control queries through each engine and writes the words that keep showing up, whatever the query was,
+// to "<engine>_blacklist.txt" so they can be used as a blacklist scoped to that engine
+pub fn build_from_engines() {
+    let (queries, min_ratio, output_directory, engine_names) = read_builder_settings();
+
+    let mut engines = engine::build_engines();
+    if !engine_names.is_empty() {
+        engines.retain(|engine| engine_names.iter().any(|name| name.eq_ignore_ascii_case(engine.name())));
+    }
+    if engines.is_empty() {
+        println!("!!!  None of the engines {:?} exist in the configuration", engine_names);
+        return;
+    }
+
+    println!("###  Building blacklists from {} control queries", queries.len());
+    for engine in &engines {
+        // which query each link was found with, so words are counted once per query however many pages had them
+        let mut link_queries: HashMap<String, usize> = HashMap::new();
+        let mut links = Vec::new();
+        for (index, query) in queries.iter().enumerate() {
+            match engine.search(query.as_str()) {
+                Ok(found) => for link in found.into_iter().map(|result| result.link) {
+                    if !link_queries.contains_key(&link) {
+                        link_queries.insert(link.clone(), index);
+                        links.push((link, Some(engine.name().to_string())));
+                    }
+                },
+                Err(e) => println!("!!!  {} failed on control query \"{}\": {}", engine, query, e)
+            }
+        }
+
+        let answered = link_queries.values().collect::<HashSet<&usize>>().len();
+        if answered < 2 {
+            println!("!!!  {} only returned results for {} control queries, which isn't enough to build a blacklist",
+                     engine, answered);
+            continue;
+        }
+
+        println!("###  {} returned {} links, dispatching spider...", engine, links.len());
+        let pages = Spider::new(links).dispatch();
+
+        let (common, threshold) = common_words(pages, &link_queries, answered, min_ratio);
+        if let Err(e) = fs::create_dir_all(&output_directory) {
+            println!("!!!  Could not create directory \"{}\": {}", output_directory.display(), e);
+            return;
+        }
+        let filename = output_directory.join(format!("{}_blacklist.txt", engine.name()));
+        match File::create(&filename) {
+            Ok(mut file) => {
+                for word in &common {
+                    if let Err(e) = writeln!(file, "{}", word) {
+                        println!("!!!  Could not write to file: {}", e);
+                    }
+                }
+                println!("$$$  Wrote {} words found with at least {} of {} control queries to \"{}\"",
+                         common.len(), threshold, answered, filename.display());
+                println!("###  It won't be used until you add this to the [blacklists] table of your configuration:");
+                println!("    [blacklists.{}]", engine.name());
+                println!("    paths = [\"{}\"]", filename.display());
+                println!("    engines = [\"{}\"]", engine.name());
+            }
+            Err(e) => println!("!!!  Could not create file \"{}\": {}", filename.display(), e)
+        }
+    }
+}
+
+// the words found with at least max(ceil(answered * min_ratio), 2) of the control queries that returned results,
+// under the first spelling they were found with, and that threshold. Words are counted once per query however many
+// of its pages had them
+fn common_words(pages: Vec<PageWords>, link_queries: &HashMap<String, usize>, answered: usize, min_ratio: f64)
+                -> (Vec<String>, usize) {
+    let mut spellings: HashMap<String, String> = HashMap::new();
+    let mut query_words: HashMap<String, HashSet<usize>> = HashMap::new();
+    for page in pages {
+        let query = match link_queries.get(&page.seed) {
+            Some(&query) => query,
+            None => continue
+        };
+        for word in page.words {
+            let folded = dedup::fold_case(word.text.as_str());
+            query_words.entry(folded.clone()).or_default().insert(query);
+            spellings.entry(folded).or_insert(word.text);
+        }
+    }
+
+    let threshold = ((answered as f64 * min_ratio).ceil() as usize).max(2);
+    let mut common: Vec<(&String, usize)> = query_words.iter()
+        .map(|(folded, found_with)| (folded, found_with.len()))
+        .filter(|&(_, count)| count >= threshold)
+        .collect();
+    // the most common first, then alphabetically so the file is the same every run
+    common.sort_by(|first, second| second.1.cmp(&first.1).then(first.0.cmp(second.0)));
+    let words = common.into_iter().map(|(folded, _)| spellings[folded].clone()).collect();
+    (words, threshold)
+}
+
+fn read_builder_settings() -> (Vec<String>, f64, PathBuf, Vec<String>) {
+    let config = configuration::CONFIGURATION.read().unwrap();
+
+    // a file of queries, one per line, wins over the ones in the configuration
+    let queries = match config.get_str("blacklist_builder.query_file") {
+        Ok(filename) => read_lines(Path::new(filename.as_str())),
+        Err(_) => match config.get_array("blacklist_builder.queries") {
+            Ok(values) => values.into_iter().filter_map(|value| value.into_str().ok()).collect(),
+            Err(_) => DEFAULT_CONTROL_QUERIES.iter().map(|query| query.to_string()).collect()
+        }
+    };
+    let min_ratio = match config.get_float("blacklist_builder.min_query_ratio") {
+        Ok(value) if value > 0.0 && value <= 1.0 => value,
+        Ok(_) => {
+            println!("!!!  \"blacklist_builder.min_query_ratio\" must be between 0 and 1. Defaulting to 0.5");
+            0.5
+        }
+        Err(_) => 0.5
+    };
+    let output_directory = PathBuf::from(config.get_str("blacklist_builder.output_directory")
+        .unwrap_or_else(|_| String::from("./blacklists/engines/")));
+    let engine_names = match config.get_array("blacklist_builder.engines") {
+        Ok(values) => values.into_iter().filter_map(|value| value.into_str().ok()).collect(),
+        Err(_) => Vec::new()
+    };
+
+    (queries, min_ratio, output_directory, engine_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::RegexBuilder;
+    use crate::parser::{Word, WordKind, WordSource};
+
+    // blacklists match ignoring case, like the RegexSet they're built into
+    fn glob_matches(glob: &str, word: &str) -> bool {
+        RegexBuilder::new(glob_to_regex(glob).as_str()).case_insensitive(true).build().unwrap().is_match(word)
+    }
+
+    fn page(url: &str, engine: Option<&str>) -> PageWords {
+        PageWords {
+            url: url.to_string(),
+            seed: url.to_string(),
+            engine: engine.map(String::from),
+            words: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(glob_matches("wiki*", "Wikipedia"));
+        assert!(glob_matches("wiki*", "wiki"));
+        assert!(!glob_matches("wiki*", "MyWiki"));
+        assert!(glob_matches("?ditor", "editor"));
+        assert!(!glob_matches("?ditor", "ditor"));
+        assert!(glob_matches("*ing", "Heading"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob_matches("[ch]at", "hat"));
+        assert!(!glob_matches("[ch]at", "bat"));
+        assert!(glob_matches("[!ch]at", "bat"));
+        assert!(!glob_matches("[!ch]at", "cat"));
+        assert!(glob_matches("file[0-9]", "file7"));
+        assert!(!glob_matches("file[0-9]", "filex"));
+        assert!(glob_matches("[]x]", "]"));
+        assert!(glob_matches("[!]x]", "y"));
+        assert!(!glob_matches("[!]x]", "]"));
+    }
+
+    #[test]
+    fn metacharacters_are_escaped() {
+        assert!(glob_matches("c++", "C++"));
+        assert!(!glob_matches("c++", "cc"));
+        assert!(glob_matches("a.b", "a.b"));
+        assert!(!glob_matches("a.b", "axb"));
+        assert!(glob_matches("(tm)|^$", "(TM)|^$"));
+        assert!(glob_matches("{1,2}\\d", "{1,2}\\d"));
+        assert!(glob_matches("[.+]", "+"));
+        assert!(!glob_matches("[.+]", "x"));
+        assert!(glob_matches("[\\^]", "^"));
+        assert!(glob_matches("[\\^]", "\\"));
+        // "&&", "--" and "~~" are set operations inside a regex class, but not in a glob
+        assert!(glob_matches("[a&&b]", "&"));
+        assert!(glob_matches("[+--]", ","));
+        assert!(glob_matches("[a~~b]", "~"));
+    }
+
+    #[test]
+    fn broken_classes_are_taken_literally() {
+        assert!(glob_matches("wiki[", "wiki["));
+        assert!(glob_matches("[z-a]*", "[z-a]*"));
+        assert!(!glob_matches("[z-a]*", "zebra"));
+    }
+
+    #[test]
+    fn blacklists_match_words_and_pages() {
+        let blacklist = Blacklist {
+            name: "test".to_string(),
+            exact: vec!["wikipedia".to_string()].into_iter().collect(),
+            patterns: Some(RegexSetBuilder::new(&[glob_to_regex("edit*")]).case_insensitive(true).build().unwrap()),
+            engines: vec!["wikipedia".to_string()],
+            domains: vec!["wikipedia.org".to_string()],
+        };
+        assert!(blacklist.contains("WIKIPEDIA"));
+        assert!(blacklist.contains("Editors"));
+        assert!(!blacklist.contains("Rocket"));
+
+        let mut words: Vec<String> = vec!["Wikipedia", "Rocket", "editing"].into_iter().map(String::from).collect();
+        blacklist.remove_from(&mut words);
+        assert_eq!(words, vec!["Rocket"]);
+
+        assert!(blacklist.is_scoped());
+        assert!(blacklist.applies_to(&page("https://example.com/", Some("Wikipedia"))));
+        assert!(blacklist.applies_to(&page("https://en.wikipedia.org/wiki/Acme", None)));
+        assert!(!blacklist.applies_to(&page("https://notwikipedia.org/", Some("google"))));
+    }
+
+    #[test]
+    fn invalid_patterns_are_skipped() {
+        let expressions: Vec<String> = vec!["^wiki", "[unclosed", "edit(or|ing)$"].into_iter().map(String::from).collect();
+        let patterns = build_patterns("test", &expressions).unwrap();
+        assert_eq!(patterns.len(), 2);
+        assert!(patterns.is_match("Wikipedia"));
+        assert!(patterns.is_match("Editing"));
+        assert!(!patterns.is_match("[unclosed"));
+        assert!(!patterns.is_match("Rocket"));
+
+        assert!(build_patterns("test", &["(".to_string()]).is_none());
+        assert!(build_patterns("test", &[]).is_none());
+    }
+
+    #[test]
+    fn common_words_need_enough_queries() {
+        let word = |text: &str| Word {
+            text: text.to_string(),
+            source: WordSource::Body,
+            kind: WordKind::Text,
+            critical: false,
+        };
+        let found = |seed: &str, words: &[&str]| PageWords {
+            url: seed.to_string(),
+            seed: seed.to_string(),
+            engine: None,
+            words: words.iter().map(|text| word(text)).collect(),
+        };
+        let link_queries: HashMap<String, usize> = vec![("a1", 0), ("a2", 0), ("b", 1), ("c", 2), ("d", 3)].into_iter()
+            .map(|(link, query)| (link.to_string(), query))
+            .collect();
+        let pages = vec![
+            // found on two pages of the same query, so it only counts once
+            found("a1", &["Privacy", "Cookies", "Banana"]),
+            found("a2", &["privacy", "Banana"]),
+            found("b", &["PRIVACY", "cookies", "Volcano"]),
+            found("c", &["Privacy", "Cookies"]),
+            found("d", &["Privacy"]),
+            found("unknown", &["Privacy", "Banana", "Banana"]),
+        ];
+        let (words, threshold) = common_words(pages, &link_queries, 4, 0.75);
+        assert_eq!(threshold, 3);
+        assert_eq!(words, vec!["Privacy", "Cookies"]);
+    }
+
+    #[test]
+    fn common_words_need_at_least_two_queries() {
+        let (words, threshold) = common_words(Vec::new(), &HashMap::new(), 2, 0.1);
+        assert_eq!(threshold, 2);
+        assert!(words.is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use reqwest::redirect::Policy;
+use reqwest::StatusCode;
+use url::Url;
+
+use super::configuration;
+use super::politeness;
+
+lazy_static! {
+    // one client for every Engine and Fetcher so connections to the same host get reused
+    pub static ref CLIENT: Client = build_client();
+    static ref RETRY_POLICY: RetryPolicy = read_retry_policy();
+    // the only directories "file:" URLs can be read from, so a results page can't point us at "file:///etc/shadow"
+    static ref LOCAL_DIRECTORIES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+}
+
+// why a request failed, so failures can be retried or summarized by kind
+#[derive(Clone, Debug, PartialEq)]
+pub enum FetchError {
+    Timeout,
+    ConnectionReset,
+    Connect(String),
+    Status(StatusCode),
+    Redirect(String),
+    Body(String),
+    Request(String),
+    File(String),
+    Panic,  // the fetcher crashed before it got an answer
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FetchError::Timeout => write!(f, "request timed out"),
+            FetchError::ConnectionReset => write!(f, "connection was reset"),
+            FetchError::Connect(e) => write!(f, "could not connect: {}", e),
+            FetchError::Status(status) => write!(f, "received status code '{}'", status),
+            FetchError::Redirect(e) => write!(f, "redirect was refused: {}", e),
+            FetchError::Body(e) => write!(f, "could not retrieve text from response: {}", e),
+            FetchError::Request(e) => write!(f, "failed to make request: {}", e),
+            FetchError::File(e) => write!(f, "could not read file: {}", e),
+            FetchError::Panic => write!(f, "the fetcher crashed"),
+        }
+    }
+}
+
+impl FetchError {
+    // timeouts, resets, rate limiting and server errors are usually gone on the next try
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FetchError::Timeout | FetchError::ConnectionReset => true,
+            FetchError::Status(status) => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            _ => false
+        }
+    }
+
+    // a short label used to group failures in the summary
+    pub fn kind(&self) -> String {
+        match self {
+            FetchError::Timeout => "timeout".to_string(),
+            FetchError::ConnectionReset => "connection reset".to_string(),
+            FetchError::Connect(_) => "connection failed".to_string(),
+            FetchError::Status(status) => format!("status {}", status.as_u16()),
+            FetchError::Redirect(_) => "redirect refused".to_string(),
+            FetchError::Body(_) => "unreadable body".to_string(),
+            FetchError::Request(_) => "request error".to_string(),
+            FetchError::File(_) => "unreadable file".to_string(),
+            FetchError::Panic => "crashed fetcher".to_string(),
+        }
+    }
+
+    fn from_reqwest(error: reqwest::Error) -> FetchError {
+        if error.is_timeout() {
+            return FetchError::Timeout;
+        }
+        // hyper buries a reset connection a few errors deep
+        let mut source = error.source();
+        while let Some(inner) = source {
+            if let Some(io_error) = inner.downcast_ref::<io::Error>() {
+                match io_error.kind() {
+                    io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof => return FetchError::ConnectionReset,
+                    io::ErrorKind::TimedOut => return FetchError::Timeout,
+                    _ => ()
+                }
+            }
+            source = inner.source();
+        }
+        if error.is_redirect() {
+            // the policy's own message is the most useful part
+            let reason = error.source().map(|inner| inner.to_string()).unwrap_or_else(|| error.to_string());
+            FetchError::Redirect(reason)
+        } else if error.is_connect() {
+            FetchError::Connect(error.to_string())
+        } else if error.is_body() || error.is_decode() {
+            FetchError::Body(error.to_string())
+        } else {
+            FetchError::Request(error.to_string())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct RetryPolicy {
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // exponential backoff with "equal jitter": somewhere between half and all of the capped delay
+    fn delay(&self, attempt: u32) -> Duration {
+        // saturating, since a big enough "max_retries" would overflow long before the cap is applied
+        let exponential = (self.base_delay.as_millis() as u64).saturating_mul(2u64.saturating_pow(attempt));
+        let capped = exponential.min(self.max_delay.as_millis() as u64);
+        let half = capped / 2;
+        Duration::from_millis(half + random_u64() % (capped - half + 1))
+    }
+}
+
+// std doesn't have a random number generator, but every RandomState is seeded with fresh random keys
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+// a successfully retrieved page and where it ended up after any redirects
+#[derive(Clone, Debug)]
+pub struct Page {
+    pub final_url: String,
+    pub text: String,
+}
+
+// GETs the URL (politely) and returns the page, retrying failures that are likely to be transient
+pub fn get_page(url: &str) -> Result<Page, FetchError> {
+    // local files from a "files" engine are read straight from disk
+    if url.starts_with("file:") {
+        return read_file_page(url);
+    }
+
+    let policy = *RETRY_POLICY;
+    let mut attempt = 0;
+    loop {
+        match try_get_page(url) {
+            Ok(page) => return Ok(page),
+            Err(e) => {
+                if !e.is_retryable() || attempt >= policy.max_retries {
+                    return Err(e);
+                }
+                let delay = policy.delay(attempt);
+                attempt += 1;
+                println!("!!!  {} failed with \"{}\", retrying in {:.1}s ({}/{})",
+                         url, e, delay.as_secs_f64(), attempt, policy.max_retries);
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+fn try_get_page(url: &str) -> Result<Page, FetchError> {
+    // wait for our turn with the host, the permit is held until the body has been read
+    let _permit = politeness::acquire(url);
+
+    // actually make the request
+    let response = CLIENT.get(url).send().map_err(FetchError::from_reqwest)?;
+    politeness::observe_response(url, &response);
+
+    // check if the status code is a 2XX
+    if !response.status().is_success() {
+        return Err(FetchError::Status(response.status()));
+    }
+
+    let final_url = response.url().to_string();
+    if configuration::read_debug() {
+        if final_url != url {
+            println!("~~~  '{}' was redirected to '{}'", url, final_url);
+        }
+        println!("~~~  Finished making the request to '{}'", url);
+    }
+
+    // get the response text (html webpage)
+    let text = response.text().map_err(FetchError::from_reqwest)?;
+    Ok(Page { final_url, text })
+}
+
+// lets "file:" URLs under the directory be read, for the engines that search local files
+pub fn allow_local_directory(directory: &Path) {
+    match fs::canonicalize(directory) {
+        Ok(directory) => LOCAL_DIRECTORIES.lock().unwrap().push(directory),
+        Err(e) => println!("!!!  \"{}\" can't be searched: {}", directory.display(), e)
+    }
+}
+
+fn read_file_page(url: &str) -> Result<Page, FetchError> {
+    let path = match Url::parse(url).ok().and_then(|parsed| parsed.to_file_path().ok()) {
+        Some(path) => path,
+        None => return Err(FetchError::File(format!("\"{}\" isn't the URL of a local file", url)))
+    };
+    // canonicalized so neither "..", nor a symlink, can lead out of the allowed directories
+    let path = fs::canonicalize(&path).map_err(|e| FetchError::File(format!("{}: {}", path.display(), e)))?;
+    if !LOCAL_DIRECTORIES.lock().unwrap().iter().any(|directory| path.starts_with(directory)) {
+        return Err(FetchError::File(format!("{} isn't in the directory of a \"files\" engine", path.display())));
+    }
+    // the page is parsed as HTML either way, so a text file that isn't quite UTF-8 is still worth reading
+    let bytes = fs::read(&path).map_err(|e| FetchError::File(format!("{}: {}", path.display(), e)))?;
+    Ok(Page {
+        final_url: url.to_string(),
+        text: String::from_utf8_lossy(&bytes).into_owned(),
+    })
+}
+
+// "www.example.com" and "example.com" are treated as the same domain
+fn same_domain(first: Option<&str>, second: Option<&str>) -> bool {
+    let strip = |host: Option<&str>| host.map(|h| h.trim_start_matches("www.").to_lowercase());
+    strip(first) == strip(second)
+}
+
+fn build_redirect_policy() -> Policy {
+    let config = configuration::CONFIGURATION.read().unwrap();
+    let max_hops = match config.get_int("redirects.max_hops") {
+        Ok(value) if value >= 0 => value as usize,
+        Ok(_) => {
+            println!("!!!  \"redirects.max_hops\" can't be negative. Defaulting to 10");
+            10
+        }
+        Err(_) => 10
+    };
+    let allow_cross_domain = config.get_bool("redirects.allow_cross_domain").unwrap_or(true);
+
+    Policy::custom(move |attempt| {
+        if attempt.previous().len() > max_hops {
+            attempt.error(format!("more than {} redirects", max_hops))
+        } else if !allow_cross_domain
+            && !same_domain(attempt.previous()[0].host_str(), attempt.url().host_str()) {
+            let message = format!("redirect to another domain ({})", attempt.url());
+            attempt.error(message)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+fn build_client() -> Client {
+    // grab user-agent to impersonate from configuration file
+    let user_agent_string = match configuration::CONFIGURATION.read().unwrap().get_str("user-agent") {
+        Ok(result) => result,
+        Err(e) => panic!("Could not read user-agent from configuration: {}", e)
+    };
+
+    // build request client that uses blocking IO and the specified user-agent
+    match Client::builder()
+        .user_agent(user_agent_string)
+        .redirect(build_redirect_policy())
+        .build() {
+            Ok(client) => {
+                if configuration::read_debug() {
+                    println!("~~~  Finished building the shared request client");
+                }
+                client
+            },
+            Err(e) => panic!("Could not create the request Client: {}", e)
+        }
+}
+
+fn read_retry_policy() -> RetryPolicy {
+    let config = configuration::CONFIGURATION.read().unwrap();
+    let read = |key: &str, default: i64| match config.get_int(key) {
+        Ok(value) if value >= 0 => value as u64,
+        Ok(_) => {
+            println!("!!!  \"{}\" can't be negative. Defaulting to {}", key, default);
+            default as u64
+        }
+        Err(_) => default as u64
+    };
+
+    RetryPolicy {
+        max_retries: read("retry.max_retries", 3) as u32,
+        base_delay: Duration::from_millis(read("retry.base_delay_ms", 500)),
+        max_delay: Duration::from_millis(read("retry.max_delay_ms", 10000)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 1000,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_millis(10000),
+        }
+    }
+
+    #[test]
+    fn delay_doubles_with_jitter() {
+        for attempt in 0..4 {
+            let full = 500 * 2u128.pow(attempt);
+            let delay = policy().delay(attempt).as_millis();
+            assert!((full / 2..=full).contains(&delay), "attempt {} waited {}ms", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn delay_is_capped_without_overflowing() {
+        for attempt in &[10, 63, 64, 500, u32::MAX] {
+            let delay = policy().delay(*attempt).as_millis();
+            assert!((5000..=10000).contains(&delay), "attempt {} waited {}ms", attempt, delay);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use std::collections::HashSet;
+
+// the form words are compared in, so "Acme", "ACME" and "acme" are all the same word
+// only ASCII letters are folded, like the eq_ignore_ascii_case the finalizer always used, so "Ärger" and "ärger" stay apart
+pub fn fold_case(word: &str) -> String {
+    word.to_ascii_lowercase()
+}
+
+// removes every word that's already in the list under a different case, keeping the first spelling
+pub fn dedup_case_insensitive(words: &mut Vec<String>) {
+    let mut seen: HashSet<String> = HashSet::with_capacity(words.len());
+    words.retain(|word| seen.insert(fold_case(word)));
+}
+
+// removes every word in the (already case-folded) blacklist
+pub fn remove_blacklisted(words: &mut Vec<String>, blacklist: &HashSet<String>) {
+    if blacklist.is_empty() {
+        return;
+    }
+    words.retain(|word| !blacklist.contains(&fold_case(word)));
+}
+
+// removes every repeat of a word with exactly the same spelling, for when the case was changed on purpose
+pub fn dedup_exact(words: &mut Vec<String>) {
+    let mut seen: HashSet<String> = HashSet::with_capacity(words.len());
+    words.retain(|word| seen.insert(word.clone()));
+}
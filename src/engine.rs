@@ -2,153 +2,572 @@ extern crate regex;
 extern crate url;
 extern crate reqwest;
 
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use config::Value;
 use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
 use url::percent_encoding::{utf8_percent_encode, DEFAULT_ENCODE_SET};
 use url::Url;
 
+use super::client;
+use super::client::FetchError;
 use super::configuration;
+use super::entities;
+use super::jsonpath::JsonPath;
+use super::parser;
+use super::parser::{Word, WordSource};
+use super::politeness;
+use super::sources;
+
+lazy_static! {
+    static ref TAG_REGEX: Regex = Regex::new(r"<[^>]*>").unwrap();
+}
+
+// a link an engine found, along with the title and snippet it showed for it (if its regex captures them)
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub link: String,
+    pub title: Option<String>,
+    pub snippet: Option<String>,
+}
+
+impl SearchResult {
+    // the words in the title and snippet, critical words first like a fetched page's
+    pub fn words(&self) -> Vec<Word> {
+        let mut critical = Vec::new();
+        let mut extracted = Vec::new();
+        // the title and snippet are separate blocks of text, so their words mustn't run together
+        for text in self.title.iter().chain(self.snippet.iter()) {
+            let (block_critical, block_extracted) = parser::parse_text(WordSource::Snippet, text.as_str());
+            critical.extend(block_critical);
+            extracted.extend(block_extracted);
+        }
+        critical.extend(extracted);
+        critical
+    }
+}
+
+// anything that turns a query into links: the Engine defined entirely in the config, or a native source (see sources.rs)
+// each is built from an [engines.*] table and runs on its own thread
+pub trait SearchSource: fmt::Display + Send {
+    // the key of the source's table in the config (e.g. "google" for [engines.google])
+    fn name(&self) -> &str;
+
+    // runs any query through the source, returning the results whose links passed the QA check
+    fn search(&self, query: &str) -> Result<Vec<SearchResult>, FetchError>;
+
+    // whether the source can give related searches to expand the queries with
+    fn suggests(&self) -> bool {
+        false
+    }
+
+    // the search suggestions and related searches the source has for a query, in the order it gave them
+    fn suggest(&self, _query: &str) -> Result<Vec<String>, FetchError> {
+        Ok(Vec::new())
+    }
+
+    // runs every query, returning each one with the links it found (or why it failed)
+    fn dispatch(&self, queries: &[String]) -> Vec<(String, Result<Vec<SearchResult>, FetchError>)> {
+        queries.iter()
+            .map(|query| (query.clone(), self.search(query.as_str())))
+            .collect()
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Engine {
-    base_url: Url,
-    link_regex: Regex
+    name: String,
+    url: String,  // kept as it was written, parsing it would percent-encode placeholders in the path
+    base_url: Url,  // what relative links are relative to
+    extractor: Extractor,
+    suggest_url: Option<String>,  // where related searches come from, the results page if it isn't set
+    suggest_regex: Option<Regex>,  // must contain a capture group called "suggestion"
+    pagination: Pagination,
+}
+
+// how to ask an engine for more than its first page of results
+// the url has a "{page}" placeholder for the page number or an "{offset}" one for the number of results to skip
+#[derive(Clone, Copy, Debug)]
+pub struct Pagination {
+    first_page: i64,  // what "{page}" is for the first page, some engines count from 0
+    first_offset: i64,  // what "{offset}" is for the first page
+    page_step: i64,  // how much "{offset}" goes up each page, usually the number of results on a page
+    max_pages: usize,
+}
+
+impl Pagination {
+    // the pagination keys in an engine's table, or only the first page if it doesn't paginate
+    fn from_table(url: &str, table: &HashMap<String, Value>) -> Pagination {
+        let int = |key: &str, default: i64| match table.get(key) {
+            Some(value) => value.clone().into_int().unwrap_or_else(|e| {
+                println!("!!!  \"{}\" isn't a valid integer: {}", key, e);
+                default
+            }),
+            None => default
+        };
+        let paginates = url.contains("{page}") || url.contains("{offset}");
+        Pagination {
+            first_page: int("first_page", 1),
+            first_offset: int("first_offset", 0),
+            page_step: int("page_step", 10),
+            max_pages: if paginates { int("max_pages", 1).max(1) as usize } else { 1 },
+        }
+    }
+}
+
+// how an engine finds the results in the response to a search
+#[derive(Clone, Debug)]
+pub enum Extractor {
+    Regex(Regex),  // over the HTML, with a "link" group and optional "title" and "snippet" groups
+    Css(CssResults, Option<Regex>),  // the regex is only used if the selectors don't find anything
+    Json(JsonResults),  // for search APIs
+}
+
+impl fmt::Display for Extractor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Extractor::Regex(regex) => write!(f, "the regex \"{}\"", regex),
+            Extractor::Css(css, _) => write!(f, "the selector \"{}\"", css.selector_text),
+            Extractor::Json(json) => write!(f, "the JSON path \"{}\"", json.results),
+        }
+    }
+}
+
+// which elements of the results page hold the links, titles and snippets
+#[derive(Clone, Debug)]
+pub struct CssResults {
+    result: Option<Selector>,  // each result on the page, the rest of the selectors are matched inside it if it's set
+    selector: Selector,
+    selector_text: String,
+    attr: String,  // the attribute of the selected element that has the link
+    title: Option<Selector>,
+    snippet: Option<Selector>,
+}
+
+impl CssResults {
+    // the selectors in a "type = \"css\"" engine's table (or any engine with a "selector")
+    fn from_table(name: &str, table: &HashMap<String, Value>) -> CssResults {
+        let selector = |key: &str| table.get(key)
+            .map(|value| value.clone().into_str().unwrap())
+            .map(|text| match Selector::parse(text.as_str()) {
+                Ok(selector) => selector,
+                Err(e) => panic!("!!!  \"{}\" in the \"{}\" engine isn't a valid CSS selector: {:?}", key, name, e)
+            });
+        CssResults {
+            result: selector("result"),
+            selector: match selector("selector") {
+                Some(selector) => selector,
+                None => panic!("!!!  The \"{}\" engine is a CSS engine without a \"selector\"", name)
+            },
+            selector_text: table.get("selector").unwrap().clone().into_str().unwrap(),
+            attr: table.get("attr").and_then(|value| value.clone().into_str().ok()).unwrap_or_else(|| String::from("href")),
+            title: selector("title"),
+            snippet: selector("snippet"),
+        }
+    }
+}
+
+// where the results and their fields are in a search API's JSON response
+#[derive(Clone, Debug)]
+pub struct JsonResults {
+    results: JsonPath,  // each result in the response
+    link: JsonPath,  // the rest are relative to the result
+    title: Option<JsonPath>,
+    snippet: Option<JsonPath>,
+    link_template: Option<String>,  // "{}" gets substituted for the link, for APIs that only give a page's name
+}
+
+impl JsonResults {
+    // the paths in a "type = \"json\"" engine's table
+    fn from_table(name: &str, table: &HashMap<String, Value>) -> JsonResults {
+        let path = |key: &str| table.get(key)
+            .map(|value| value.clone().into_str().unwrap())
+            .map(|text| match JsonPath::parse(text.as_str()) {
+                Ok(path) => path,
+                Err(e) => panic!("!!!  \"{}\" in the \"{}\" engine isn't a valid JSON path: {}", key, name, e)
+            });
+        JsonResults {
+            results: path("results").unwrap_or_else(|| JsonPath::parse("$[*]").unwrap()),
+            link: match path("link") {
+                Some(link) => link,
+                None => panic!("!!!  The \"{}\" engine is a JSON engine without a \"link\" path", name)
+            },
+            title: path("title"),
+            snippet: path("snippet"),
+            link_template: table.get("link_template").and_then(|value| value.clone().into_str().ok()),
+        }
+    }
 }
 
 impl fmt::Display for Engine {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Engine \"{}\"", self.base_url)
+        write!(f, "Engine \"{}\"", self.url)
     }
 }
 
 impl Engine {
-    fn new(base_url: String, contents_pattern: String) -> Engine {
+    fn new(name: String, base_url: String, extractor: Extractor,
+           suggest_url: Option<String>, suggest_pattern: Option<String>, pagination: Pagination) -> Engine {
+        // the templates are checked here so a typo fails at startup rather than on every search
+        Url::parse(suggest_url.as_ref().unwrap_or(&base_url).as_str()).unwrap();
         Engine {
+            name,
             base_url: Url::parse(base_url.as_str()).unwrap(),
-            link_regex: Regex::new(
-                contents_pattern.as_str()
-            ).unwrap(),
+            url: base_url,
+            extractor,
+            suggest_url,
+            suggest_regex: suggest_pattern.map(|pattern| Regex::new(pattern.as_str()).unwrap()),
+            pagination,
         }
     }
 
-    pub fn dispatch(&self) -> Result<Vec<String>, String> {
-        let query_as_string = match configuration::CONFIGURATION.read().unwrap().get_str("query") {
-            Ok(result) => result.clone(),
-            Err(e) => panic!("Could not read query from configuration: {}", e)
+    // a regex, CSS or JSON engine from its table in the config
+    fn from_table(name: String, kind: &str, table: &HashMap<String, Value>) -> Engine {
+        let url = match table.get("url") {
+            Some(url) => url.clone().into_str().unwrap(),
+            None => panic!("!!!  The \"{}\" engine doesn't have a \"url\"", name)
+        };
+        let pagination = Pagination::from_table(url.as_str(), table);
+        let regex = table.get("regex")
+            .map(|value| Regex::new(value.clone().into_str().unwrap().as_str()).unwrap());
+        let extractor = match kind {
+            "json" => Extractor::Json(JsonResults::from_table(name.as_str(), table)),
+            "css" => Extractor::Css(CssResults::from_table(name.as_str(), table), regex),
+            _ => match regex {
+                Some(regex) => Extractor::Regex(regex),
+                None => panic!("!!!  The \"{}\" engine doesn't have a \"regex\"", name)
+            }
+        };
+        Engine::new(
+            name,
+            url,
+            extractor,
+            table.get("suggest_url").and_then(|value| value.clone().into_str().ok()),
+            table.get("suggest_regex").and_then(|value| value.clone().into_str().ok()),
+            pagination)
+    }
+}
+
+impl SearchSource for Engine {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    // only engines with a "suggest_regex" can find related searches
+    fn suggests(&self) -> bool {
+        self.suggest_regex.is_some()
+    }
+
+    fn suggest(&self, query: &str) -> Result<Vec<String>, FetchError> {
+        let suggest_regex = match &self.suggest_regex {
+            Some(regex) => regex,
+            None => return Ok(Vec::new())
         };
-        let query = query_as_string.as_str();
+        let url = self.search_url(self.suggest_url.as_ref().unwrap_or(&self.url), query, 0);
+        if configuration::read_debug() {
+            println!("~~~  {} asking for suggestions from {:?}", self, url);
+        }
 
+        let response_text = client::get_page(url.as_str())?.text;
+        Ok(extract_suggestions(suggest_regex, response_text.as_str()))
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<SearchResult>, FetchError> {
         println!("###  {} dispatched with query: {}", self, query);
-        let encoded_query = utf8_percent_encode(query, DEFAULT_ENCODE_SET).to_string();
-        let url = self.base_url.as_str().replace("{}", encoded_query.as_str());
+        self.collect_pages(query, read_max_links(), |url| self.generate_links(url))
+    }
+}
 
-        match self.generate_links(url.as_str()) {
-            Ok(mut links) => {
-                if configuration::read_debug() {
-                    println!("{} full url = {:?}", self, url);
-                    println!("{} extracted the following links with the regex \"{}\":", self, self.link_regex);
-                    for link in &links {
-                        println!("{}", link);
-                    }
+impl Engine {
+    // engines with pagination keep asking for the next page until one has nothing new or there are enough links
+    // each page's URL is handed to fetch_page, which returns the links on it
+    fn collect_pages<F>(&self, query: &str, max_links: Option<usize>, mut fetch_page: F) -> Result<Vec<SearchResult>, FetchError>
+        where F: FnMut(&str) -> Result<Vec<SearchResult>, FetchError> {
+        let mut links = Vec::new();
+        let mut seen = HashSet::new();
+        for page in 0..self.pagination.max_pages {
+            let url = self.search_url(&self.url, query, page);
+            let mut page_links = match fetch_page(url.as_str()) {
+                Ok(page_links) => page_links,
+                // the pages we already have are still good if a later one fails
+                Err(e) if page > 0 => {
+                    println!("!!!  {} failed on page {} of \"{}\", keeping the earlier pages: {}", self, page + 1, query, e);
+                    break;
+                }
+                Err(e) => return Err(e)
+            };
+            if configuration::read_debug() {
+                println!("{} full url = {:?}", self, url);
+                println!("{} extracted the following links with {}:", self, self.extractor);
+                for result in &page_links {
+                    println!("{}", result.link);
                 }
+            }
 
-                filter_links(&mut links, query);
-                let max_links = match configuration::CONFIGURATION.read() {
-                    Ok(config) => {
-                        match config.get_int("sensitivity.max_links") {
-                            Ok(max) =>
-                                if max != -1 && max as usize <= links.len() {
-                                    println!("###  {} received {} links but will only return the first {}", self, links.len(), max);
-                                    max as usize
-                                } else {
-                                    links.len()
-                                },
-                            Err(_) => links.len()
-                        }
-                    }
-                    Err(_) => links.len()
-                };
+            // engines often repeat results on later pages, or the last page forever once they run out
+            page_links.retain(|result| seen.insert(result.link.clone()));
+            if page_links.is_empty() {
+                if page > 0 && configuration::read_debug() {
+                    println!("~~~  {} had no new links on page {} of \"{}\"", self, page + 1, query);
+                }
+                break;
+            }
 
-                Ok(links[0..max_links].to_vec())
+            filter_links(&mut page_links, query);
+            links.extend(page_links);
+            if let Some(max) = max_links {
+                if max <= links.len() {
+                    println!("###  {} received {} links but will only return the first {}", self, links.len(), max);
+                    links.truncate(max);
+                    break;
+                }
             }
-            Err(e) => Err(e)
         }
+
+        Ok(links)
     }
 
-    //runs actual search via search engine
-    fn generate_links(&self, url: &str) -> Result<Vec<String>, String> {
+    // the template with the query and page filled in ("{}", then "{page}" or "{offset}" if it paginates)
+    fn search_url(&self, template: &str, query: &str, page: usize) -> String {
+        let encoded_query = utf8_percent_encode(query, DEFAULT_ENCODE_SET).to_string();
+        let page_number = self.pagination.first_page + page as i64;
+        let offset = self.pagination.first_offset + page as i64 * self.pagination.page_step;
+        template
+            .replace("{page}", page_number.to_string().as_str())
+            .replace("{offset}", offset.to_string().as_str())
+            .replace("{}", encoded_query.as_str())
+    }
 
-        // grab user-agent to impersonate from configuration file
-        let user_agent_string = match configuration::CONFIGURATION.read().unwrap().get_str("user-agent") {
-            Ok(result) => result.clone(),
-            Err(e) => panic!("Could not read user-agent from configuration: {}", e)
-        };
+    //runs actual search via search engine
+    fn generate_links(&self, url: &str) -> Result<Vec<SearchResult>, FetchError> {
+        // make the request, retrying anything that looks transient
+        let response_text = client::get_page(url)?.text;
 
-        // build request client that uses blocking IO and the specified user-agent
-        let request_client = match reqwest::blocking::Client::builder()
-            .user_agent(user_agent_string)
-            .build() {
-                Ok(client) => {
-                    client
-                },
-                Err(e) => panic!("Could not create Engine Client: {}", e)
-            };
-        
         if configuration::read_debug() {
-            println!("~~~  {} has finished building the request client", self);
+            println!("~~~  {} has received the response text", self);
+            println!("\n\n\n{}\n\n\n", response_text);
         }
 
-        // actually make the request
-        let response = match request_client.get(url).send() {
-            Ok(resp) => resp,
-            Err(e) => return Err(format!("!!!  {} failed to make request due to error: {}", self, e))
-        };
+        // extract the links to each page from the HTML
+        Ok(self.extract_links(response_text))  // return the vector of absolute URLs
+    }
 
-        // check if the status code is a 2XX
-        if response.status() != reqwest::StatusCode::OK {
-            return Err(format!("!!!  {} made request but received status code '{}'", self, response.status()))
+    //returns a vector of the URLs, with their titles and snippets if the engine finds them
+    fn extract_links(&self, search_results: String) -> Vec<SearchResult> {
+        match &self.extractor {
+            Extractor::Regex(link_regex) => self.extract_with_regex(link_regex, search_results.as_str()),
+            Extractor::Css(css, fallback) => {
+                let links = self.extract_with_selectors(css, search_results.as_str());
+                match fallback {
+                    // the selectors finding nothing usually means the engine changed its markup
+                    Some(link_regex) if links.is_empty() => {
+                        println!("!!!  {} found no links with {}, falling back to the regex", self, self.extractor);
+                        self.extract_with_regex(link_regex, search_results.as_str())
+                    }
+                    _ => links
+                }
+            }
+            Extractor::Json(json) => {
+                let root: serde_json::Value = match serde_json::from_str(search_results.as_str()) {
+                    Ok(root) => root,
+                    Err(e) => {
+                        println!("!!!  {} didn't return valid JSON: {}", self, e);
+                        return Vec::new();
+                    }
+                };
+                json.results.select(&root)
+                    .into_iter()
+                    .filter_map(|result| {
+                        let link = json.link.select_string(result)?;
+                        let link = match &json.link_template {
+                            Some(template) => template.replace("{}", utf8_percent_encode(link.as_str(), DEFAULT_ENCODE_SET).to_string().as_str()),
+                            None => link
+                        };
+                        Some(SearchResult {
+                            link: self.absolute_link(link)?,
+                            // APIs still highlight matches with HTML and escape text as entities sometimes
+                            title: json.title.as_ref().and_then(|title| title.select_string(result)).map(|title| clean_capture(title.as_str())),
+                            snippet: json.snippet.as_ref().and_then(|snippet| snippet.select_string(result)).map(|snippet| clean_capture(snippet.as_str())),
+                        })
+                    })
+                    .collect()
+            }
         }
+    }
 
-        if configuration::read_debug() {
-            println!("~~~  {} has finished making the request to '{}'", self, url);
+    fn extract_with_regex(&self, link_regex: &Regex, search_results: &str) -> Vec<SearchResult> {
+        link_regex
+            .captures_iter(search_results)
+            .filter_map(|cap| Some(SearchResult {
+                // the capture is raw HTML, so "&amp;" and friends have to be decoded before it's a usable URL
+                link: self.absolute_link(entities::decode_entities(cap.name("link").unwrap().as_str()))?,
+                title: cap.name("title").map(|title| clean_capture(title.as_str())),
+                snippet: cap.name("snippet").map(|snippet| clean_capture(snippet.as_str())),
+            }))
+            .collect()
+    }
+
+    fn extract_with_selectors(&self, css: &CssResults, search_results: &str) -> Vec<SearchResult> {
+        let document = Html::parse_document(search_results);
+        let mut links = Vec::new();
+        match &css.result {
+            Some(result_selector) => {
+                // a result without a link (e.g. an ad or a "people also ask" box) is skipped
+                for result in document.select(result_selector) {
+                    let link = result.select(&css.selector)
+                        .filter_map(|element| element.value().attr(css.attr.as_str()))
+                        .filter_map(|link| self.absolute_link(link.to_string()))
+                        .next();
+                    if let Some(link) = link {
+                        links.push(SearchResult {
+                            link,
+                            title: css.title.as_ref().and_then(|title| result.select(title).next()).map(element_text),
+                            snippet: css.snippet.as_ref().and_then(|snippet| result.select(snippet).next()).map(element_text),
+                        });
+                    }
+                }
+            }
+            None => {
+                for element in document.select(&css.selector) {
+                    if let Some(link) = element.value().attr(css.attr.as_str()).and_then(|link| self.absolute_link(link.to_string())) {
+                        links.push(SearchResult {
+                            link,
+                            title: None,
+                            snippet: None,
+                        });
+                    }
+                }
+            }
         }
 
-        // get the response text (html webpage)
-        let response_text = match response.text() {
-            Ok(text) => text,
-            Err(e) => return Err(format!("!!!  {} could not retrieve text from response due to error: {}", self, e))
-        };
+        links
+    }
 
-        if configuration::read_debug() {
-            println!("~~~  {} has received the response text", self);
-            println!("\n\n\n{}\n\n\n", response_text);
+    // None for anything that isn't a web page, e.g. a "javascript:" or "file:" link planted in the results
+    fn absolute_link(&self, link: String) -> Option<String> {
+        match self.base_url.join(link.as_str()) { //try joining the link with the base_url
+            Ok(full_url) => {
+                if configuration::read_debug() {
+                    println!("RL: {}", link);
+                }
+                web_link(full_url.as_str())
+            } //if sucessful, the link was relative
+            Err(_) => web_link(link.as_str()), //otherwise its absolute and only has to be checked
         }
+    }
+}
 
-        // extract the links to each page from the HTML
-        Ok(self.extract_links(response_text))  // return the vector of absolute URLs
+// the link if it's an http(s) URL, the only kind of link from someone else's content that should ever be fetched
+pub fn web_link(link: &str) -> Option<String> {
+    match Url::parse(link) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Some(url.to_string()),
+        _ => None
     }
+}
 
-    //returns a vector of the URLs
-    fn extract_links(&self, search_results: String) -> Vec<String> {
-        let links =
-            self.link_regex
-                .captures_iter(search_results.as_str())
-                .map(|cap| match self.base_url.join(cap.name("link").unwrap().as_str()) { //try joining the link with the base_url
-                    Ok(full_url) => {
-                        if configuration::read_debug() {
-                            println!("RL: {}", cap.name("link").unwrap().as_str());
-                        }
-                        full_url.to_string()
-                    } //if sucessful, the link was relative
-                    Err(_) => cap.name("link").unwrap().as_str().to_string(), //otherwise its absolute and can be returned
-                })
-                .collect();
+// the text of an element with its whitespace collapsed, the parser has already decoded the entities
+fn element_text(element: ElementRef) -> String {
+    element.text().collect::<Vec<&str>>().join(" ").split_whitespace().collect::<Vec<&str>>().join(" ")
+}
 
-        links
+// the text in a capture from a results page, which is often highlighted ("<b>acme</b> rockets") and full of entities
+pub fn clean_capture(html: &str) -> String {
+    let text = entities::decode_entities(TAG_REGEX.replace_all(html, " ").as_ref());
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// the "suggestion" captures in a response, without their tags and entities
+fn extract_suggestions(suggest_regex: &Regex, response_text: &str) -> Vec<String> {
+    suggest_regex
+        .captures_iter(response_text)
+        .filter_map(|cap| cap.name("suggestion"))
+        .map(|suggestion| clean_capture(suggestion.as_str()))
+        .filter(|suggestion| !suggestion.is_empty())
+        .collect()
+}
+
+// runs the queries' suggestions as extra queries, and their suggestions in turn, up to "expansion.max_depth" rounds
+// returns the original queries followed by the new ones, without any repeats
+pub fn expand_queries(engines: &[Box<dyn SearchSource>], queries: &[String]) -> Vec<String> {
+    let (max_depth, fan_out) = match configuration::CONFIGURATION.read() {
+        Ok(config) => (
+            match config.get_int("expansion.max_depth") {
+                Ok(value) if value >= 0 => value as usize,
+                _ => 0
+            },
+            match config.get_int("expansion.fan_out") {
+                Ok(value) if value >= 0 => value as usize,
+                _ => 5
+            }
+        ),
+        Err(e) => panic!("!!!  Configuration could not be read : {}", e)
+    };
+
+    expand_with(engines, queries, max_depth, fan_out)
+}
+
+// takes up to fan_out new suggestions from each engine for each query, for up to max_depth rounds
+fn expand_with(engines: &[Box<dyn SearchSource>], queries: &[String], max_depth: usize, fan_out: usize) -> Vec<String> {
+    let mut expanded: Vec<String> = queries.to_vec();
+    if max_depth == 0 || fan_out == 0 {
+        return expanded;
+    }
+    let suggesting: Vec<&dyn SearchSource> = engines.iter()
+        .map(|engine| engine.as_ref())
+        .filter(|engine| engine.suggests())
+        .collect();
+    if suggesting.is_empty() {
+        println!("!!!  Query expansion is on, but none of the engines have a \"suggest_regex\"");
+        return expanded;
+    }
+
+    // suggestions often only differ from the query (or each other) by case
+    let mut seen: HashSet<String> = queries.iter().map(|query| query.to_lowercase()).collect();
+    let mut current_round: Vec<String> = queries.to_vec();
+    for depth in 1..=max_depth {
+        let mut next_round = Vec::new();
+        for query in &current_round {
+            for engine in &suggesting {
+                let suggestions = match engine.suggest(query.as_str()) {
+                    Ok(suggestions) => suggestions,
+                    Err(e) => {
+                        println!("!!!  {} failed to give suggestions for \"{}\": {}", engine, query, e);
+                        continue;
+                    }
+                };
+                // only the engine's first few suggestions are used, the later ones drift away from the query
+                for suggestion in suggestions.into_iter()
+                    .filter(|suggestion| seen.insert(suggestion.to_lowercase()))
+                    .take(fan_out) {
+                    println!("###  Expanded \"{}\" to \"{}\" (depth {})", query, suggestion, depth);
+                    next_round.push(suggestion);
+                }
+            }
+        }
+        if next_round.is_empty() {
+            break;
+        }
+        expanded.extend(next_round.iter().cloned());
+        current_round = next_round;
     }
+
+    println!("$$$  Expanded {} queries to {}", queries.len(), expanded.len());
+    expanded
 }
 
-fn filter_links(links: &mut Vec<String>, query: &str) {
+// the most links a source should return for a query ("sensitivity.max_links"), if there's a limit
+pub fn read_max_links() -> Option<usize> {
+    match configuration::CONFIGURATION.read() {
+        Ok(config) => match config.get_int("sensitivity.max_links") {
+            Ok(max) if max != -1 => Some(max.max(0) as usize),
+            _ => None
+        },
+        Err(_) => None
+    }
+}
+
+pub fn filter_links(links: &mut Vec<SearchResult>, query: &str) {
     let page_rex = Regex::new(r"https?://[^/]+/(?P<page>.+)$").unwrap();
     let alphanumeric_rex = Regex::new(r"[a-zA-Z0-9]+").unwrap();
 
@@ -220,7 +639,8 @@ fn filter_links(links: &mut Vec<String>, query: &str) {
     };
 
 
-    links.retain(|link| {
+    links.retain(|result| {
+        let link = &result.link;
         if configuration::read_debug() {
             println!("###  Original link {:?} before ignoring patterns", link);
         }
@@ -378,24 +798,36 @@ fn filter_links(links: &mut Vec<String>, query: &str) {
     });
 }
 
-pub fn build_engines() -> Vec<Engine> {
-    let mut engines: Vec<Engine> = Vec::new();
+// every source in the [engines] tables, built from the table's "type"
+pub fn build_engines() -> Vec<Box<dyn SearchSource>> {
+    let mut engines: Vec<Box<dyn SearchSource>> = Vec::new();
 
     match configuration::CONFIGURATION.read() {
         Ok(config) => {
             match config.get_table("engines") {
                 Ok(table_list) => {
-                    for table
+                    for (name, table)
                         in
                         table_list
-                            .values()
-                            .map(|table| table.clone().into_table().unwrap())
+                            .iter()
+                            .map(|(name, table)| (name.clone(), table.clone().into_table().unwrap()))
                         {
-                            engines.push(
-                                Engine::new(
-                                    table.get("url").unwrap().clone().into_str().unwrap(),
-                                    table.get("regex").unwrap().clone().into_str().unwrap())
-                            );
+                            // any rate limit keys in the engine's table override the global ones for its host
+                            if let Some(url) = table.get("url").and_then(|value| value.clone().into_str().ok()) {
+                                politeness::set_host_limits(url.as_str(), &table);
+                            }
+                            // engines with a selector are CSS engines unless they say otherwise, and keep any regex as a fallback
+                            let kind = table.get("type").and_then(|value| value.clone().into_str().ok())
+                                .unwrap_or_else(|| String::from(if table.contains_key("selector") { "css" } else { "regex" }));
+                            let engine: Box<dyn SearchSource> = match kind.as_str() {
+                                "regex" | "css" | "json" => Box::new(Engine::from_table(name, kind.as_str(), &table)),
+                                "files" => Box::new(sources::FileIndex::from_table(name, &table)),
+                                "sitemap" => Box::new(sources::Sitemap::from_table(name, &table)),
+                                "mediawiki" => Box::new(sources::MediaWiki::from_table(name, &table)),
+                                _ => panic!("!!!  The \"{}\" engine has an unknown type \"{}\", it should be \"regex\", \"css\", \
+                                    \"json\", \"files\", \"sitemap\" or \"mediawiki\"", name, kind)
+                            };
+                            engines.push(engine);
                         }
                 }
                 Err(_) => panic!("!!!  The \"engines\" table and its relevant sub-tables are missing from the configuration.")
@@ -407,4 +839,355 @@ pub fn build_engines() -> Vec<Engine> {
     println!("$$$  Successfully built {} search Engines", engines.len());
 
     engines
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // the engine's debug output reads the configuration, which the tests don't load
+    fn quiet() {
+        configuration::CONFIGURATION.write().unwrap().set("debug", false).unwrap();
+    }
+
+    fn pagination(first_page: i64, first_offset: i64, page_step: i64, max_pages: usize) -> Pagination {
+        Pagination {
+            first_page,
+            first_offset,
+            page_step,
+            max_pages,
+        }
+    }
+
+    fn engine(url: &str, pagination: Pagination) -> Engine {
+        let regex = Regex::new(r#"<a href="(?P<link>[^"]+)">"#).unwrap();
+        Engine::new("test".to_string(), url.to_string(), Extractor::Regex(regex), None, None, pagination)
+    }
+
+    fn results(links: &[&str]) -> Vec<SearchResult> {
+        links.iter()
+            .map(|link| SearchResult {
+                link: link.to_string(),
+                title: None,
+                snippet: None,
+            })
+            .collect()
+    }
+
+    fn links(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.link.as_str()).collect()
+    }
+
+    // suggests whatever it was given for each query
+    struct Suggester {
+        suggestions: HashMap<String, Vec<String>>,
+    }
+
+    impl fmt::Display for Suggester {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "Suggester")
+        }
+    }
+
+    impl SearchSource for Suggester {
+        fn name(&self) -> &str {
+            "suggester"
+        }
+
+        fn search(&self, _query: &str) -> Result<Vec<SearchResult>, FetchError> {
+            Ok(Vec::new())
+        }
+
+        fn suggests(&self) -> bool {
+            true
+        }
+
+        fn suggest(&self, query: &str) -> Result<Vec<String>, FetchError> {
+            Ok(self.suggestions.get(query).cloned().unwrap_or_default())
+        }
+    }
+
+    fn suggester(suggestions: &[(&str, &[&str])]) -> Box<dyn SearchSource> {
+        Box::new(Suggester {
+            suggestions: suggestions.iter()
+                .map(|(query, suggested)| (query.to_string(), suggested.iter().map(|text| text.to_string()).collect()))
+                .collect(),
+        })
+    }
+
+    fn strings(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[test]
+    fn expansion_respects_the_fan_out_and_depth() {
+        let engines = vec![suggester(&[
+            ("acme", &["Acme", "acme rockets", "acme anvils", "acme magnets"]),
+            ("acme rockets", &["acme rockets skates", "acme"]),
+            ("acme anvils", &["acme anvils heavy"]),
+            ("acme rockets skates", &["too deep"]),
+        ])];
+        let queries = strings(&["acme"]);
+
+        // repeats (in any case) are skipped before the fan-out is counted
+        assert_eq!(expand_with(&engines, &queries, 1, 2), strings(&["acme", "acme rockets", "acme anvils"]));
+        assert_eq!(expand_with(&engines, &queries, 2, 2),
+                   strings(&["acme", "acme rockets", "acme anvils", "acme rockets skates", "acme anvils heavy"]));
+        // it stops early once a round has nothing new
+        assert_eq!(expand_with(&engines, &queries, 10, 1), strings(&["acme", "acme rockets", "acme rockets skates", "too deep"]));
+        assert_eq!(expand_with(&engines, &queries, 0, 5), queries);
+        assert_eq!(expand_with(&engines, &queries, 3, 0), queries);
+    }
+
+    #[test]
+    fn suggestions_are_cleaned_up() {
+        let regex = Regex::new(r#"<suggestion data="(?P<suggestion>[^"]*)"/>"#).unwrap();
+        let response = r#"<toplevel><CompleteSuggestion><suggestion data="acme rockets"/></CompleteSuggestion>
+            <CompleteSuggestion><suggestion data="acme &amp; sons"/></CompleteSuggestion>
+            <CompleteSuggestion><suggestion data=""/></CompleteSuggestion></toplevel>"#;
+        assert_eq!(extract_suggestions(&regex, response), strings(&["acme rockets", "acme & sons"]));
+
+        let related = Regex::new(r#"<a class="related">(?P<suggestion>.+?)</a>"#).unwrap();
+        let page = r#"<a class="related">acme <b>rocket</b> skates</a> <a class="related">wile e. coyote</a>"#;
+        assert_eq!(extract_suggestions(&related, page), strings(&["acme rocket skates", "wile e. coyote"]));
+    }
+
+    #[test]
+    fn expansion_needs_an_engine_that_suggests() {
+        let engines: Vec<Box<dyn SearchSource>> = vec![Box::new(engine("https://acme.example/?q={}", pagination(1, 0, 10, 1)))];
+        assert_eq!(expand_with(&engines, &strings(&["acme"]), 2, 5), strings(&["acme"]));
+    }
+
+    #[test]
+    fn regex_finds_links_titles_and_snippets() {
+        quiet();
+        let regex = Regex::new(r#"(?s)<div class="r"><a href="(?P<link>[^"]+)"><h3>(?P<title>.*?)</h3></a><p>(?P<snippet>.*?)</p>"#).unwrap();
+        let results_engine = Engine::new("test".to_string(), "https://search.example/?q={}".to_string(), Extractor::Regex(regex),
+                                         None, None, pagination(1, 0, 10, 1));
+        let page = r#"<div class="r"><a href="https://acme.example/rockets?a=1&amp;b=2"><h3><b>Acme</b> Rockets</h3></a><p>Rocket skates &amp; more</p></div>
+            <div class="r"><a href="/local/anvils"><h3>Anvils</h3></a><p></p></div>
+            <div class="r"><a href="javascript:alert(1)"><h3>Planted</h3></a><p>nothing</p></div>"#;
+        let found = results_engine.extract_links(page.to_string());
+        assert_eq!(links(&found), vec!["https://acme.example/rockets?a=1&b=2", "https://search.example/local/anvils"]);
+        assert_eq!(found[0].title.as_deref(), Some("Acme Rockets"));
+        assert_eq!(found[0].snippet.as_deref(), Some("Rocket skates & more"));
+        assert_eq!(found[1].snippet.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn result_words_come_from_the_title_and_snippet() {
+        let result = SearchResult {
+            link: "https://acme.example/".to_string(),
+            title: Some("Acme Rockets".to_string()),
+            snippet: Some("Genuine explosives, shipped overnight".to_string()),
+        };
+        let words = result.words();
+        assert!(words.iter().all(|word| word.source == WordSource::Snippet));
+        let texts: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+        // critical words come first, and the title's last word isn't joined to the snippet's first
+        assert_eq!(texts.first(), Some(&"Acme"));
+        assert!(texts.contains(&"AcmeRockets") && texts.contains(&"explosives") && texts.contains(&"overnight"));
+        assert!(!texts.iter().any(|text| text.contains("RocketsGenuine")));
+        let first_plain = words.iter().position(|word| !word.critical).unwrap();
+        assert!(words[first_plain..].iter().all(|word| !word.critical));
+
+        let empty = SearchResult {
+            link: "https://acme.example/".to_string(),
+            title: None,
+            snippet: None,
+        };
+        assert!(empty.words().is_empty());
+    }
+
+    fn table(entries: &[(&str, &str)]) -> HashMap<String, Value> {
+        entries.iter().map(|(key, value)| (key.to_string(), Value::from(*value))).collect()
+    }
+
+    #[test]
+    fn json_finds_links_titles_and_snippets() {
+        quiet();
+        let api = Engine::from_table("api".to_string(), "json", &table(&[
+            ("url", "https://api.example/search?q={}"),
+            ("results", "$.data.items[*]"),
+            ("link", "url"),
+            ("title", "meta.title"),
+            ("snippet", "summary"),
+        ]));
+        let body = r#"{"data": {"items": [
+            {"url": "https://acme.example/rockets", "meta": {"title": "Acme <em>Rockets</em>"}, "summary": "Skates &amp; more"},
+            {"url": "/relative/anvils"},
+            {"title": "no link"},
+            {"url": "file:///etc/passwd", "meta": {"title": "Planted"}}
+        ]}}"#;
+        let found = api.extract_links(body.to_string());
+        assert_eq!(links(&found), vec!["https://acme.example/rockets", "https://api.example/relative/anvils"]);
+        assert_eq!(found[0].title.as_deref(), Some("Acme Rockets"));
+        assert_eq!(found[0].snippet.as_deref(), Some("Skates & more"));
+        assert_eq!((found[1].title.as_ref(), found[1].snippet.as_ref()), (None, None));
+
+        assert!(api.extract_links("<html>not json</html>".to_string()).is_empty());
+        assert!(api.extract_links(r#"{"data": {}}"#.to_string()).is_empty());
+    }
+
+    #[test]
+    fn json_links_can_fill_in_a_template() {
+        quiet();
+        // a MediaWiki search only gives the titles of its pages
+        let wiki = Engine::from_table("wiki".to_string(), "json", &table(&[
+            ("url", "https://wiki.example/w/api.php?action=query&list=search&format=json&srsearch={}"),
+            ("results", "$.query.search[*]"),
+            ("link", "title"),
+            ("link_template", "https://wiki.example/wiki/{}"),
+        ]));
+        let found = wiki.extract_links(r#"{"query": {"search": [{"title": "Acme Corporation"}, {"title": "Wile E. Coyote"}]}}"#.to_string());
+        assert_eq!(links(&found), vec!["https://wiki.example/wiki/Acme%20Corporation", "https://wiki.example/wiki/Wile%20E.%20Coyote"]);
+
+        // without a "results" path the response is a list of results
+        let list = Engine::from_table("list".to_string(), "json", &table(&[("url", "https://api.example/?q={}"), ("link", "href")]));
+        let found = list.extract_links(r#"[{"href": "https://acme.example/1"}, {"href": "https://acme.example/2"}]"#.to_string());
+        assert_eq!(links(&found), vec!["https://acme.example/1", "https://acme.example/2"]);
+    }
+
+    const CSS_RESULTS: &str = r#"<html><body>
+        <div class="g"><div class="r"><a href="https://acme.example/rockets">Acme Rockets</a><a href="https://cache.example/acme">Cached</a></div>
+            <h3>Acme <b>Rockets</b></h3><div class="s">Rocket   skates &amp; more</div></div>
+        <div class="g ad"><h3>An ad without a result link</h3></div>
+        <div class="g"><div class="r"><a href="/local/anvils">Anvils</a></div><h3>Anvils</h3></div>
+        <div class="g"><div class="r"><a href="javascript:void(0)">Planted</a></div></div>
+        </body></html>"#;
+
+    #[test]
+    fn css_finds_links_titles_and_snippets_in_each_result() {
+        quiet();
+        let css = Engine::from_table("css".to_string(), "css", &table(&[
+            ("url", "https://search.example/?q={}"),
+            ("result", "div.g"),
+            ("selector", "div.r > a"),
+            ("title", "h3"),
+            ("snippet", "div.s"),
+        ]));
+        let found = css.extract_links(CSS_RESULTS.to_string());
+        // only the first link of each result, and the results without one are skipped
+        assert_eq!(links(&found), vec!["https://acme.example/rockets", "https://search.example/local/anvils"]);
+        assert_eq!(found[0].title.as_deref(), Some("Acme Rockets"));
+        assert_eq!(found[0].snippet.as_deref(), Some("Rocket skates & more"));
+        assert_eq!((found[1].title.as_deref(), found[1].snippet.as_deref()), (Some("Anvils"), None));
+    }
+
+    #[test]
+    fn css_without_a_result_takes_every_selected_link() {
+        quiet();
+        let css = Engine::from_table("css".to_string(), "css", &table(&[
+            ("url", "https://search.example/?q={}"),
+            ("selector", "div.r a"),
+        ]));
+        let found = css.extract_links(CSS_RESULTS.to_string());
+        assert_eq!(links(&found), vec![
+            "https://acme.example/rockets", "https://cache.example/acme", "https://search.example/local/anvils",
+        ]);
+        assert!(found.iter().all(|result| result.title.is_none() && result.snippet.is_none()));
+
+        let data = Engine::from_table("css".to_string(), "css", &table(&[
+            ("url", "https://search.example/?q={}"),
+            ("selector", "li[data-url]"),
+            ("attr", "data-url"),
+        ]));
+        let found = data.extract_links(r#"<ul><li data-url="https://acme.example/">Acme</li><li>none</li></ul>"#.to_string());
+        assert_eq!(links(&found), vec!["https://acme.example/"]);
+    }
+
+    #[test]
+    fn css_falls_back_to_the_regex() {
+        quiet();
+        let css = Engine::from_table("css".to_string(), "css", &table(&[
+            ("url", "https://search.example/?q={}"),
+            ("selector", "div.moved > a"),
+            ("regex", r#"<a href="(?P<link>https://acme[^"]+)">"#),
+        ]));
+        assert_eq!(links(&css.extract_links(CSS_RESULTS.to_string())), vec!["https://acme.example/rockets"]);
+
+        let no_fallback = Engine::from_table("css".to_string(), "css", &table(&[
+            ("url", "https://search.example/?q={}"),
+            ("selector", "div.moved > a"),
+        ]));
+        assert!(no_fallback.extract_links(CSS_RESULTS.to_string()).is_empty());
+    }
+
+    #[test]
+    fn search_url_fills_in_the_placeholders() {
+        let paged = engine("https://acme.example/search?q={}&p={page}&start={offset}", pagination(0, 1, 20, 3));
+        assert_eq!(paged.search_url(&paged.url, "rocket skates", 0), "https://acme.example/search?q=rocket%20skates&p=0&start=1");
+        assert_eq!(paged.search_url(&paged.url, "rocket skates", 2), "https://acme.example/search?q=rocket%20skates&p=2&start=41");
+
+        // placeholders in the path aren't mangled by parsing the template
+        let path = engine("https://acme.example/{page}/search/{}", pagination(1, 0, 10, 3));
+        assert_eq!(path.search_url(&path.url, "anvils", 1), "https://acme.example/2/search/anvils");
+        assert_eq!(path.to_string(), "Engine \"https://acme.example/{page}/search/{}\"");
+
+        // a suggestion template without any paging
+        assert_eq!(path.search_url("https://acme.example/suggest?q={}", "anvils", 0), "https://acme.example/suggest?q=anvils");
+    }
+
+    #[test]
+    fn paging_stops_on_a_page_without_new_links() {
+        quiet();
+        let paged = engine("https://acme.example/search?q={}&page={page}", pagination(1, 0, 10, 5));
+        let requested = RefCell::new(Vec::new());
+        let found = paged.collect_pages("acme rockets", None, |url| {
+            requested.borrow_mut().push(url.to_string());
+            Ok(match requested.borrow().len() {
+                1 => results(&["https://acme.example/acme-rockets", "https://acme.example/rockets-acme"]),
+                2 => results(&["https://acme.example/rockets-acme", "https://acme.example/acme-rockets-2"]),
+                // the engine ran out and repeats its last page
+                3 => results(&["https://acme.example/acme-rockets-2"]),
+                _ => results(&["https://acme.example/acme-rockets-4"]),
+            })
+        }).unwrap();
+        assert_eq!(links(&found), vec![
+            "https://acme.example/acme-rockets", "https://acme.example/rockets-acme", "https://acme.example/acme-rockets-2",
+        ]);
+        assert_eq!(requested.into_inner(), vec![
+            "https://acme.example/search?q=acme%20rockets&page=1",
+            "https://acme.example/search?q=acme%20rockets&page=2",
+            "https://acme.example/search?q=acme%20rockets&page=3",
+        ]);
+    }
+
+    #[test]
+    fn paging_stops_at_max_pages_and_max_links() {
+        quiet();
+        let paged = engine("https://acme.example/search?q={}&start={offset}", pagination(1, 0, 10, 2));
+        let mut pages = 0;
+        let found = paged.collect_pages("acme", None, |_| {
+            pages += 1;
+            Ok(results(&[format!("https://acme.example/acme-{}", pages).as_str()]))
+        }).unwrap();
+        assert_eq!(links(&found), vec!["https://acme.example/acme-1", "https://acme.example/acme-2"]);
+
+        let mut pages = 0;
+        let found = paged.collect_pages("acme", Some(1), |_| {
+            pages += 1;
+            Ok(results(&["https://acme.example/acme-1", "https://acme.example/acme-2"]))
+        }).unwrap();
+        assert_eq!(pages, 1);
+        assert_eq!(links(&found), vec!["https://acme.example/acme-1"]);
+    }
+
+    #[test]
+    fn a_failed_later_page_keeps_the_earlier_ones() {
+        quiet();
+        let paged = engine("https://acme.example/search?q={}&page={page}", pagination(1, 0, 10, 3));
+        let mut pages = 0;
+        let found = paged.collect_pages("acme", None, |_| {
+            pages += 1;
+            match pages {
+                1 => Ok(results(&["https://acme.example/acme"])),
+                _ => Err(FetchError::Panic)
+            }
+        }).unwrap();
+        assert_eq!(links(&found), vec!["https://acme.example/acme"]);
+
+        assert!(paged.collect_pages("acme", None, |_| Err(FetchError::Panic)).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::char;
+
+use html5ever::data::{C1_REPLACEMENTS, NAMED_ENTITIES};
+
+// the longest named entity is "&CounterClockwiseContourIntegral;"
+const MAX_ENTITY_NAME_LENGTH: usize = 32;
+
+// decodes every named ("&eacute;"), decimal ("&#233;") and hex ("&#x00E9;") character reference
+// the way a browser would, for text that didn't come through the DOM (e.g. regex captures)
+pub fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(position) = rest.find('&') {
+        result.push_str(&rest[..position]);
+        rest = &rest[position..];
+
+        let decoded = if rest[1..].starts_with('#') {
+            decode_numeric(rest)
+        } else {
+            decode_named(rest)
+        };
+        match decoded {
+            Some((characters, consumed)) => {
+                result.push_str(&characters);
+                rest = &rest[consumed..];
+            }
+            None => {
+                // not a character reference, so the '&' is just text
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+// returns the decoded characters and how many bytes of the input they replace
+fn decode_numeric(reference: &str) -> Option<(String, usize)> {
+    let (radix, digits_start) = match reference[2..].chars().next() {
+        Some('x') | Some('X') => (16, 3),
+        _ => (10, 2)
+    };
+    let digits_length = reference[digits_start..]
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(reference.len() - digits_start);
+    if digits_length == 0 {
+        return None;
+    }
+
+    let digits = &reference[digits_start..digits_start + digits_length];
+    let mut consumed = digits_start + digits_length;
+    if reference[consumed..].starts_with(';') {
+        consumed += 1;
+    }
+
+    // anything too big to parse is out of range anyway
+    let code_point = u32::from_str_radix(digits, radix).unwrap_or(u32::MAX);
+    let character = match code_point {
+        0 => '\u{FFFD}',
+        // windows-1252 characters that are often written with their byte value
+        0x80..=0x9F => C1_REPLACEMENTS[(code_point - 0x80) as usize]
+            .unwrap_or_else(|| char::from_u32(code_point).unwrap()),
+        _ => char::from_u32(code_point).unwrap_or('\u{FFFD}')
+    };
+
+    Some((character.to_string(), consumed))
+}
+
+fn decode_named(reference: &str) -> Option<(String, usize)> {
+    let name_length = reference[1..]
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(reference.len() - 1)
+        .min(MAX_ENTITY_NAME_LENGTH);
+    if name_length == 0 {
+        return None;
+    }
+    let name = &reference[1..1 + name_length];
+
+    // the full name with its semicolon is the usual case
+    if reference[1 + name_length..].starts_with(';') {
+        if let Some(characters) = lookup(&reference[1..name_length + 2]) {
+            return Some((characters, name_length + 2));
+        }
+    }
+
+    // otherwise fall back to the longest legacy entity that doesn't need a semicolon (e.g. "&copy2020")
+    for length in (1..=name_length).rev() {
+        if let Some(characters) = lookup(&name[..length]) {
+            return Some((characters, length + 1));
+        }
+    }
+
+    None
+}
+
+fn lookup(name: &str) -> Option<String> {
+    match NAMED_ENTITIES.get(name) {
+        // the table also holds every prefix of every name, which map to nothing
+        Some(&(0, _)) | None => None,
+        Some(&(first, second)) => {
+            let mut characters = String::new();
+            characters.extend(char::from_u32(first));
+            if second != 0 {
+                characters.extend(char::from_u32(second));
+            }
+            Some(characters)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_references() {
+        assert_eq!(decode_entities("Caf&eacute; &amp; Bar"), "Café & Bar");
+        assert_eq!(decode_entities("&lt;b&gt;"), "<b>");
+        // some names are two characters
+        assert_eq!(decode_entities("&NotEqualTilde;"), "\u{2242}\u{338}");
+    }
+
+    #[test]
+    fn numeric_references() {
+        assert_eq!(decode_entities("&#233;t&#xE9; &#XE9;"), "été é");
+        assert_eq!(decode_entities("&#65&#x42;C"), "ABC");
+        assert_eq!(decode_entities("&#x1F680;"), "\u{1F680}");
+        // windows-1252 byte values, and the ones that aren't in it
+        assert_eq!(decode_entities("&#128;&#x99;"), "\u{20AC}\u{2122}");
+        assert_eq!(decode_entities("&#x81;"), "\u{81}");
+    }
+
+    #[test]
+    fn invalid_code_points_are_replaced() {
+        assert_eq!(decode_entities("&#0;"), "\u{FFFD}");
+        assert_eq!(decode_entities("&#xD800;"), "\u{FFFD}");
+        assert_eq!(decode_entities("&#x110000;"), "\u{FFFD}");
+        assert_eq!(decode_entities("&#99999999999999999999;"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn unterminated_references() {
+        assert_eq!(decode_entities("AT&T"), "AT&T");
+        assert_eq!(decode_entities("fish & chips &"), "fish & chips &");
+        assert_eq!(decode_entities("&#"), "&#");
+        assert_eq!(decode_entities("&#x;"), "&#x;");
+        assert_eq!(decode_entities("&#;"), "&#;");
+        assert_eq!(decode_entities("&;"), "&;");
+        // legacy entities work without their semicolon, like in a browser
+        assert_eq!(decode_entities("&copy2020 &amp"), "\u{A9}2020 &");
+        assert_eq!(decode_entities("&notit;"), "\u{AC}it;");
+    }
+
+    #[test]
+    fn unknown_names_are_left_alone() {
+        // unless they start with a legacy entity
+        assert_eq!(decode_entities("&bogus; &Eacutex;"), "&bogus; \u{C9}x;");
+        assert_eq!(decode_entities("&zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz;"), "&zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz;");
+        assert_eq!(decode_entities("no references"), "no references");
+    }
+}
@@ -1,16 +1,39 @@
 use std::fmt;
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
 use std::fs::File;
 use std::io::Write;
 use regex::Regex;
+use scraper::Html;
 
+use super::client;
+use super::client::{FetchError, Page};
 use super::configuration;
 use super::parser;
+use super::parser::Word;
+
+// everything a Fetcher reports back to the Spider over the shared channel
+#[derive(Clone, Debug)]
+pub enum FetcherMessage {
+    // every word found on a page, in one go so they can be counted per page
+    // along with the link the crawl started from (as given to the Spider) and the engine that found it
+    Words { url: String, seed: String, engine: Option<String>, words: Vec<Word> },
+    // links found on a fetched page, along with the page the crawl started from (where it ended up after redirects),
+    // the link it started from, the engine that found it, and the depth they are at
+    Links { origin: String, seed: String, engine: Option<String>, depth: u32, links: Vec<String> },
+    Fetched { url: String, final_url: String },
+    Failed { url: String, error: FetchError },
+    Finished,
+}
 
 #[derive(Clone, Debug)]
 pub struct Fetcher {
     url: String,
-    transmitter: Arc<Mutex<mpsc::Sender<String>>>,
+    origin: String,
+    seed: String,  // the link the crawl started from, before any redirects
+    engine: Option<String>,  // the engine that found the page the crawl started from, if any
+    depth: u32,
+    follow_links: bool,  // whether the links on the page are sent to the Spider to be crawled
+    transmitter: Arc<Mutex<mpsc::Sender<FetcherMessage>>>,
 }
 
 impl fmt::Display for Fetcher {
@@ -20,9 +43,20 @@ impl fmt::Display for Fetcher {
 }
 
 impl Fetcher {
-    pub fn new(url: String, transmitter: Arc<Mutex<mpsc::Sender<String>>>) -> Fetcher {
+    pub fn new(url: String,
+               origin: String,
+               seed: String,
+               engine: Option<String>,
+               depth: u32,
+               follow_links: bool,
+               transmitter: Arc<Mutex<mpsc::Sender<FetcherMessage>>>) -> Fetcher {
         Fetcher {
             url,
+            origin,
+            seed,
+            engine,
+            depth,
+            follow_links,
             transmitter,
         }
     }
@@ -34,30 +68,59 @@ impl Fetcher {
 
         // retrieve the URL
         match self.get_url() {
-            Ok(response) => {
+            Ok(page) => {
 
                 if configuration::read_debug() {
                     println!("~~~  {} got the URL with an OK", self);
                 }
-                
+
+                // build the DOM once for both the links and the words
+                let document = Html::parse_document(&page.text);
+
+                // relative links are relative to wherever we got redirected to
+                let links = if self.follow_links {
+                    parser::extract_links(&document, page.final_url.as_str())
+                } else {
+                    Vec::new()
+                };
+
                 // parse the webpage and get the list of words
-                let results = parser::parse(&mut response.clone());
+                let results = parser::parse(&document);
 
                 // grab a lock on the transmitter to the Spider
-                let unlocked_tx = self.transmitter.lock().unwrap();
+                let unlocked_tx = self.lock_transmitter();
+
+                // let the Spider know where the page actually ended up
+                unlocked_tx.send(FetcherMessage::Fetched {
+                    url: self.url.clone(),
+                    final_url: page.final_url.clone(),
+                }).unwrap();
 
                 if configuration::read_debug() {
                     println!("~~~  {} collected vectors of length {} & {}", self, results.0.len(), results.1.len());
                 }
 
-                // transmit the list of 'critical words' first
-                for item in results.0 {
-                    unlocked_tx.send(item).unwrap();
-                }
-
-                // then transmit the list of normal words for the wordlist
-                for item in results.1 {
-                    unlocked_tx.send(item).unwrap();
+                // transmit the list of 'critical words' first, then the list of normal words for the wordlist
+                let mut words = results.0;
+                words.extend(results.1);
+                unlocked_tx.send(FetcherMessage::Words {
+                    url: self.url.clone(),
+                    seed: self.seed.clone(),
+                    engine: self.engine.clone(),
+                    words,
+                }).unwrap();
+
+                // and finally hand any links back so the Spider can decide whether to crawl them
+                if !links.is_empty() {
+                    // a crawl starting on a redirected page is scoped to where it was redirected to
+                    let origin = if self.depth == 0 { page.final_url.clone() } else { self.origin.clone() };
+                    unlocked_tx.send(FetcherMessage::Links {
+                        origin,
+                        seed: self.seed.clone(),
+                        engine: self.engine.clone(),
+                        depth: self.depth + 1,
+                        links,
+                    }).unwrap();
                 }
 
                 if configuration::read_debug() {
@@ -65,13 +128,36 @@ impl Fetcher {
                 }
                 
             }
-            Err(e) => println!("!!!  {} encountered an error: {}", self, e)
+            Err(e) => {
+                println!("!!!  {} encountered an error: {}", self, e);
+                // let the Spider know so it can be counted in the summary
+                self.lock_transmitter().send(FetcherMessage::Failed { url: self.url.clone(), error: e }).unwrap();
+            }
         }
 
-        println!("$$$  {} has completed sucessfully", self);
+        println!("$$$  {} has completed", self);
+
+        // always tell the Spider we're done, even on an error, so it knows when the crawl is over
+        if let Err(e) = self.lock_transmitter().send(FetcherMessage::Finished) {
+            println!("!!!  {} could not notify the spider it finished: {}", self, e)
+        }
     }
 
-    fn get_url(&self) -> Result<String, String> {
+    // tells the Spider this fetcher failed and is finished, for when it panicked before it could
+    pub fn abandon(&self) {
+        println!("!!!  {} crashed", self);
+        let unlocked_tx = self.lock_transmitter();
+        // the Spider may already be gone, and then there's no one left to tell
+        let _ = unlocked_tx.send(FetcherMessage::Failed { url: self.url.clone(), error: FetchError::Panic });
+        let _ = unlocked_tx.send(FetcherMessage::Finished);
+    }
+
+    // a fetcher panicking while it held the lock doesn't stop the others from reporting back
+    fn lock_transmitter(&self) -> MutexGuard<'_, mpsc::Sender<FetcherMessage>> {
+        self.transmitter.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn get_url(&self) -> Result<Page, FetchError> {
 
         if configuration::read_debug() {
             println!("~~~ {} started to get URL", self);
@@ -80,46 +166,8 @@ impl Fetcher {
         // turn the URL into a str object for ease of reference
         let target_url = self.url.as_str();
 
-        // grab user-agent to impersonate from configuration file
-        let user_agent_string = match configuration::CONFIGURATION.read().unwrap().get_str("user-agent") {
-            Ok(result) => result.clone(),
-            Err(e) => panic!("Could not read user-agent from configuration: {}", e)
-        };
-
-        // build request client that uses blocking IO and the specified user-agent
-        let request_client = match reqwest::blocking::Client::builder()
-            .user_agent(user_agent_string)
-            .build() {
-                Ok(client) => {
-                    client
-                },
-                Err(e) => panic!("Could not create Fetcher Client: {}", e)
-            };
-        
-        if configuration::read_debug() {
-            println!("~~~  {} has finished building the request client", self);
-        }
-
-        // actually make the request
-        let response = match request_client.get(target_url).send() {
-            Ok(resp) => resp,
-            Err(e) => return Err(format!("!!!  {} failed to make request due to error: {}", self, e))
-        };
-
-        // check if the status code is a 2XX
-        if response.status() != reqwest::StatusCode::OK {
-            return Err(format!("!!!  {} made request but received status code '{}'", self, response.status()))
-        }
-
-        if configuration::read_debug() {
-            println!("~~~  {} has finished making the request to '{}'", self, target_url);
-        }
-
-        // get the response text (html webpage)
-        let response_text = match response.text() {
-            Ok(text) => text,
-            Err(e) => return Err(format!("!!!  {} could not retrieve text from response due to error: {}", self, e))
-        };
+        // make the request, retrying anything that looks transient
+        let page = client::get_page(target_url)?;
 
         if configuration::read_debug() {
             println!("~~~  {} has successfully retrieved the response text", self);
@@ -133,7 +181,7 @@ impl Fetcher {
             println!("~~~~~ to file: {}", debug_filename);
             match File::create(format!("{}.html", debug_filename)) {
                 Ok(mut file) => {
-                    match write!(file, "{}\n", response_text) {
+                    match writeln!(file, "{}", page.text) {
                         Ok(_) => (),
                         Err(e) => println!("!!!  Spider debug could not write special debug wordlist: {}", e)
                     }
@@ -143,6 +191,6 @@ impl Fetcher {
         }
 
         // return the content of the webpage in an Ok
-        Ok(response_text)
+        Ok(page)
     }
 }
\ No newline at end of file
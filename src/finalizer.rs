@@ -1,23 +1,36 @@
+use super::blacklist;
 use super::configuration;
+use super::dedup;
+use super::mangler;
+use super::profile;
+use super::ranking;
+use super::ranking::RankingPoints;
+use super::parser::{Word, WordKind, WordSource};
+use super::spider::PageWords;
 
 use config::Value;
 
-use std::fs;
 use std::fs::File;
-use std::io::{BufReader, BufRead, Write};
+use std::io::Write;
+use std::path::Path;
 use std::process::Command;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::RandomState;
 
-pub fn finish_link_vector(link_vector: Vec<String>) {
+// writes each link, followed by a tab and where it was redirected to if it didn't end up where it started
+pub fn finish_link_vector(link_vector: Vec<(String, Option<String>)>) {
     match configuration::CONFIGURATION.read() {
         Ok(config) => {
             match config.get_str("filenames.links") {
                 Ok(value) => {
                     match File::create(&value) {
                         Ok(mut file) => {
-                            for link in link_vector {
-                                match write!(file, "{}\n", link) {
+                            for (link, final_url) in link_vector {
+                                let line = match final_url {
+                                    Some(final_url) if final_url != link => format!("{}\t{}", link, final_url),
+                                    _ => link
+                                };
+                                match writeln!(file, "{}", line) {
                                     Ok(_) => (),
                                     Err(e) => println!("!!!  Could not write to file: {}", e)
                                 }
@@ -34,7 +47,129 @@ pub fn finish_link_vector(link_vector: Vec<String>) {
     }
 }
 
-pub fn finish_wordlist(end_list: &mut Vec<String>) {
+// how much a word counts for, based on where on the page it was found and what kind of token it is
+struct SourceWeights {
+    sources: HashMap<WordSource, f64>,
+    kinds: HashMap<WordKind, f64>,
+    critical: f64,
+}
+
+impl SourceWeights {
+    fn weigh(&self, word: &Word) -> f64 {
+        let source_weight = self.sources.get(&word.source).cloned().unwrap_or(1.0)
+            * self.kinds.get(&word.kind).cloned().unwrap_or(1.0);
+        if word.critical {
+            source_weight * self.critical
+        } else {
+            source_weight
+        }
+    }
+}
+
+fn read_source_weights() -> SourceWeights {
+    let config = configuration::CONFIGURATION.read().unwrap();
+    let mut sources = HashMap::new();
+    for source in WordSource::ALL.iter() {
+        let weight = config.get_float(format!("weights.{}", source.name()).as_str()).unwrap_or(1.0);
+        sources.insert(*source, weight);
+    }
+
+    // text is what everything else is relative to, so only the other kinds have a multiplier
+    let mut kinds = HashMap::new();
+    for kind in [WordKind::Number, WordKind::Date, WordKind::Pairing].iter() {
+        let weight = config.get_float(format!("weights.{}", kind.name()).as_str()).unwrap_or(1.0);
+        kinds.insert(*kind, weight);
+    }
+
+    SourceWeights {
+        sources,
+        kinds,
+        critical: config.get_float("weights.critical").unwrap_or(1.0),
+    }
+}
+
+fn read_ranking_points() -> RankingPoints {
+    let config = configuration::CONFIGURATION.read().unwrap();
+    RankingPoints {
+        page: config.get_float("ranking.page_points").unwrap_or(10.0),
+        occurrence: config.get_float("ranking.occurrence_points").unwrap_or(1.0),
+    }
+}
+
+// turns the harvested words into a list of spellings, the highest scoring ones first
+fn rank_words(pages: Vec<PageWords>, weights: &SourceWeights, points: &RankingPoints) -> Vec<String> {
+    let weighed = pages.into_iter()
+        .map(|page| page.words.into_iter().map(|word| {
+            let weight = weights.weigh(&word);
+            (word.text, weight)
+        }));
+    let ranked = ranking::rank(weighed, points);
+
+    if configuration::read_debug() {
+        println!("~~~  Highest scoring words:");
+        for word_stats in ranked.iter().take(25) {
+            println!("~~~    {:.1}  {} (pages: {}, occurrences: {}, weight: {:.1})",
+                     points.score(word_stats), word_stats.spelling, word_stats.pages, word_stats.occurrences, word_stats.best_weight);
+        }
+    }
+
+    ranked.into_iter().map(|word_stats| word_stats.spelling).collect()
+}
+
+// blacklists, ranks, mangles and filters the words from the pages into the final wordlist
+fn build_wordlist(mut pages: Vec<PageWords>) -> Vec<String> {
+    let blacklists = blacklist::read_blacklists();
+
+    // scoped blacklists only apply to the words of some pages, so they're removed before the pages are merged
+    println!("###  Removing words blacklisted for specific engines and domains");
+    blacklist::filter_pages(&mut pages, &blacklists);
+
+    // the mangling presets can be limited to these and the profile is combined with them, so they're remembered before the pages are merged
+    let critical_words: HashSet<String> = pages.iter()
+        .flat_map(|page| page.words.iter())
+        .filter(|word| word.critical)
+        .map(|word| dedup::fold_case(word.text.as_str()))
+        .collect();
+
+    println!("###  Ranking words by how often and where they were found");
+    // ranking already merges the spellings of a word, so the list comes back deduped
+    let mut end_list = rank_words(pages, &read_source_weights(), &read_ranking_points());
+
+    if configuration::read_debug() {
+        println!("###  Wordlist is now of length: {}", end_list.len());
+    }
+
+    println!("###  Removing blacklisted words");
+    for blacklist in blacklists.iter().filter(|blacklist| !blacklist.is_scoped()) {
+        blacklist.remove_from(&mut end_list);
+    }
+
+    if let Some(profile) = profile::read_profile() {
+        let top_words: Vec<String> = end_list.iter()
+            .filter(|word| critical_words.contains(&dedup::fold_case(word)))
+            .cloned()
+            .collect();
+        let mut candidates = profile::generate(&profile, &top_words, &profile::read_settings());
+        println!("###  Target profile produced {} candidates", candidates.len());
+        // the target's own details are better guesses than anything scraped, so they go first
+        candidates.extend(end_list);
+        end_list = candidates;
+        // "wile" and "Wile" are both candidates on purpose, so only exact repeats are removed
+        dedup::dedup_exact(&mut end_list);
+    }
+
+    let rules = mangler::read_rules();
+    let presets = mangler::read_presets();
+    if !rules.is_empty() || presets.is_some() {
+        println!("###  Mangling {} words", end_list.len());
+        end_list = mangler::mangle(&end_list, &rules, presets.as_ref(), &critical_words);
+        // the rules and presets change the case on purpose, so only exact repeats are removed
+        dedup::dedup_exact(&mut end_list);
+        if configuration::read_debug() {
+            println!("###  Mangled wordlist is of length: {}", end_list.len());
+        }
+    }
+
     let minimum_word_length
         = match configuration::CONFIGURATION.read().unwrap().get_int("minimum_word_length") {
         Ok(value) => value as usize,
@@ -54,81 +189,25 @@ pub fn finish_wordlist(end_list: &mut Vec<String>) {
         }
     };
 
-    end_list.retain(|s| s.len() <= maximum_word_length && s.len() >= minimum_word_length);
-
-    println!("###  Deduping list");
-    // dedup
-    for start in 0..end_list.len() {
-        let mut end = start;
-        while end < end_list.len() { //use a while loop rather than for since removing changes index
-            if start != end && end_list[start].eq_ignore_ascii_case(end_list[end].as_str()) {
-                end_list.remove(end);
-            } else {
-                end += 1;
-            }
-        }
-    }
+    // lengths are in characters, not bytes, so accented words aren't penalized
+    end_list.retain(|s| {
+        let length = s.chars().count();
+        length <= maximum_word_length && length >= minimum_word_length
+    });
 
-    if configuration::read_debug() {
-        println!("###  Wordlist is now of length: {}", end_list.len());
-    }
-
-    println!("###  Removing blacklisted words");
-    match fs::read_dir("./blacklists/") {
-        Ok(contents) => {
-            for entry in contents {
-                let path = entry.unwrap().path();
-                let filename = path.to_str().unwrap();
-                match File::open(filename) {
-                    Ok(blacklist_file) => {
-                        if configuration::read_debug() {
-                            println!("###  Using blacklist from path: {}", filename);
-                        }
-                        let reader = BufReader::new(blacklist_file);
-
-                        for line in reader.lines() {
-                            let entry = line.unwrap();
-                            let mut index: usize = 0;
+    println!("$$$  wordlist was finalized to length {}", end_list.len());
 
-                            while index < end_list.len() {
-                                if entry.eq_ignore_ascii_case(end_list[index].as_str()) {
-                                    end_list.remove(index);
-                                    break; //because the list doesn't have duplicates we can break
-                                }
-                                index += 1;
-                            }
-                        }
-                    }
-                    Err(e) => println!("!!!  Blacklist file could not be opened: {}", e)
-                }
-            }
-        }
-        Err(_) => println!("!!!  Directory \"./blacklists/\" is missing")
-    }
+    end_list
+}
 
-    println!("$$$  wordlist was finalized to length {}", end_list.len());
+pub fn finish_wordlist(pages: Vec<PageWords>) {
+    let end_list = build_wordlist(pages);
 
     //then write to file
     match configuration::CONFIGURATION.read() {
         Ok(config) => {
             match config.get_str("filenames.wordlist") {
-                Ok(value) => {
-                    match File::create(&value) {
-                        Ok(mut file) => {
-                            if configuration::read_debug() {
-                                println!("~~~  Writing wordlist of length: {}", end_list.len());
-                            }
-                            
-                            for word in end_list {
-                                match write!(file, "{}\n", word) {
-                                    Ok(_) => (),
-                                    Err(e) => println!("!!!  Could not write to file: {}", e)
-                                }
-                            }
-                        }
-                        Err(e) => println!("!!!  Could not create file \"{}\": {}", value, e)
-                    }
-                }
+                Ok(value) => write_wordlist(&end_list, value.as_str()),
                 Err(_) => println!("The \"wordlist\" key is missing \
                     from the \"filenames\" table in config.toml.\
                     The word list won't be processed into a file... \
@@ -139,6 +218,55 @@ pub fn finish_wordlist(end_list: &mut Vec<String>) {
     }
 }
 
+
+// a wordlist for each query from only the pages it found, e.g. "wordlist_acme_rockets.txt" for "Acme Rockets"
+pub fn finish_query_wordlists(pages: &[PageWords], queries: &[String], link_queries: &HashMap<String, Vec<String>>) {
+    let filename = configuration::CONFIGURATION.read().unwrap()
+        .get_str("filenames.wordlist")
+        .unwrap_or_else(|_| String::from("wordlist.txt"));
+    let path = Path::new(filename.as_str());
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("wordlist");
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("txt");
+
+    for query in queries {
+        // pages found by crawling belong to every query that found the link the crawl started from
+        let query_pages: Vec<PageWords> = pages.iter()
+            .filter(|page| match link_queries.get(&page.seed) {
+                Some(found_with) => found_with.contains(query),
+                None => false
+            })
+            .cloned()
+            .collect();
+        println!("###  Finalizing wordlist for query \"{}\" from {} pages", query, query_pages.len());
+
+        let slug: String = query.to_lowercase()
+            .chars()
+            .map(|character| if character.is_alphanumeric() { character } else { '_' })
+            .collect();
+        let query_filename = path.with_file_name(format!("{}_{}.{}", stem, slug, extension));
+        let end_list = build_wordlist(query_pages);
+        write_wordlist(&end_list, query_filename.to_str().unwrap_or(filename.as_str()));
+    }
+}
+
+fn write_wordlist(end_list: &[String], filename: &str) {
+    match File::create(filename) {
+        Ok(mut file) => {
+            if configuration::read_debug() {
+                println!("~~~  Writing wordlist of length: {}", end_list.len());
+            }
+
+            for word in end_list {
+                match writeln!(file, "{}", word) {
+                    Ok(_) => (),
+                    Err(e) => println!("!!!  Could not write to file: {}", e)
+                }
+            }
+        }
+        Err(e) => println!("!!!  Could not create file \"{}\": {}", filename, e)
+    }
+}
+
 pub fn run_post_processing() {
     match configuration::CONFIGURATION.read() {
         Ok(config) => {
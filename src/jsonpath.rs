@@ -0,0 +1,256 @@
+use std::fmt;
+use serde_json::Value;
+
+// one step of a path, e.g. "query", "[0]", "[*]" or "..url"
+#[derive(Clone, Debug)]
+enum Step {
+    Key(String),
+    Index(i64),  // negative indexes count from the end, "[-1]" is the last element
+    Wildcard,  // every element of an array or value of an object
+    Descendants(Option<String>),  // "..key" is every "key" at any depth, "..*" is everything below
+}
+
+// a small subset of JSONPath, enough to find the results in a search API's response
+// e.g. "$.query.search[*]", "$.hits.hits[*]._source.url", "results[0].title" or "$..url"
+#[derive(Clone, Debug)]
+pub struct JsonPath {
+    text: String,
+    steps: Vec<Step>,
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl JsonPath {
+    pub fn parse(text: &str) -> Result<JsonPath, String> {
+        let mut steps = Vec::new();
+        let characters: Vec<char> = text.trim().chars().collect();
+        let mut position = 0;
+        if characters.first() == Some(&'$') {
+            position += 1;
+        } else if !characters.is_empty() && characters[0] != '.' && characters[0] != '[' {
+            // relative paths can start with a key, "title" is the same as "$.title"
+            let (key, end) = read_key(&characters, position);
+            steps.push(Step::Key(key));
+            position = end;
+        }
+
+        while position < characters.len() {
+            match characters[position] {
+                '.' if characters.get(position + 1) == Some(&'.') => {
+                    let (key, end) = read_key(&characters, position + 2);
+                    if key.is_empty() {
+                        return Err(format!("\"..\" needs a key or \"*\" after it at character {}", position + 1));
+                    }
+                    steps.push(Step::Descendants(if key == "*" { None } else { Some(key) }));
+                    position = end;
+                }
+                '.' => {
+                    let (key, end) = read_key(&characters, position + 1);
+                    match key.as_str() {
+                        "" => return Err(format!("missing key after \".\" at character {}", position + 1)),
+                        "*" => steps.push(Step::Wildcard),
+                        _ => steps.push(Step::Key(key))
+                    }
+                    position = end;
+                }
+                '[' => {
+                    let close = match characters[position..].iter().position(|character| *character == ']') {
+                        Some(offset) => position + offset,
+                        None => return Err(format!("unclosed \"[\" at character {}", position + 1))
+                    };
+                    let inside: String = characters[position + 1..close].iter().collect();
+                    let inside = inside.trim();
+                    if inside == "*" {
+                        steps.push(Step::Wildcard);
+                    } else if (inside.starts_with('\'') && inside.ends_with('\'') && inside.len() >= 2)
+                        || (inside.starts_with('"') && inside.ends_with('"') && inside.len() >= 2) {
+                        steps.push(Step::Key(inside[1..inside.len() - 1].to_string()));
+                    } else {
+                        match inside.parse::<i64>() {
+                            Ok(index) => steps.push(Step::Index(index)),
+                            Err(_) => return Err(format!("\"[{}]\" isn't an index, \"*\", or a quoted key", inside))
+                        }
+                    }
+                    position = close + 1;
+                }
+                other => return Err(format!("unexpected \"{}\" at character {}", other, position + 1))
+            }
+        }
+
+        Ok(JsonPath {
+            text: text.to_string(),
+            steps,
+        })
+    }
+
+    // every value the path leads to, in document order
+    pub fn select<'a>(&self, root: &'a Value) -> Vec<&'a Value> {
+        let mut current = vec![root];
+        for step in &self.steps {
+            let mut next = Vec::new();
+            for value in current {
+                match step {
+                    Step::Key(key) => {
+                        if let Some(child) = value.get(key.as_str()) {
+                            next.push(child);
+                        }
+                    }
+                    Step::Index(index) => {
+                        if let Value::Array(array) = value {
+                            let index = if *index < 0 { array.len() as i64 + index } else { *index };
+                            if index >= 0 {
+                                if let Some(child) = array.get(index as usize) {
+                                    next.push(child);
+                                }
+                            }
+                        }
+                    }
+                    Step::Wildcard => next.extend(children(value)),
+                    Step::Descendants(key) => collect_descendants(value, key.as_ref(), &mut next),
+                }
+            }
+            current = next;
+        }
+
+        current
+    }
+
+    // the first value the path leads to as text, for the fields of a result
+    pub fn select_string(&self, root: &Value) -> Option<String> {
+        self.select(root).into_iter().filter_map(value_to_string).next()
+    }
+}
+
+// a key runs until the next "." or "["
+fn read_key(characters: &[char], start: usize) -> (String, usize) {
+    let mut end = start;
+    while end < characters.len() && characters[end] != '.' && characters[end] != '[' {
+        end += 1;
+    }
+    (characters[start..end].iter().collect::<String>().trim().to_string(), end)
+}
+
+fn children(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(array) => array.iter().collect(),
+        Value::Object(object) => object.values().collect(),
+        _ => Vec::new()
+    }
+}
+
+fn collect_descendants<'a>(value: &'a Value, key: Option<&String>, found: &mut Vec<&'a Value>) {
+    for (child_key, child) in keyed_children(value) {
+        match key {
+            Some(key) if child_key != Some(key.as_str()) => (),
+            _ => found.push(child)
+        }
+        collect_descendants(child, key, found);
+    }
+}
+
+// an object's values with their keys, or an array's elements without any
+fn keyed_children(value: &Value) -> Vec<(Option<&str>, &Value)> {
+    match value {
+        Value::Array(array) => array.iter().map(|child| (None, child)).collect(),
+        Value::Object(object) => object.iter().map(|(key, child)| (Some(key.as_str()), child)).collect(),
+        _ => Vec::new()
+    }
+}
+
+// strings as they are and numbers and booleans written out, since some APIs give IDs as numbers
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(boolean) => Some(boolean.to_string()),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response() -> Value {
+        json!({
+            "query": {
+                "search": [
+                    {"title": "Acme Corporation", "pageid": 101, "featured": true},
+                    {"title": "Wile E. Coyote", "pageid": 102},
+                    {"title": "Road Runner", "pageid": 103, "url": "https://example.com/road-runner"}
+                ]
+            },
+            "hits": {"hits": [{"_source": {"url": "https://example.com/acme"}}]},
+            "dotted.key": "found"
+        })
+    }
+
+    fn strings(path: &str, root: &Value) -> Vec<String> {
+        JsonPath::parse(path).unwrap().select(root).into_iter().filter_map(value_to_string).collect()
+    }
+
+    #[test]
+    fn keys() {
+        let root = response();
+        assert_eq!(strings("$.hits.hits[0]._source.url", &root), vec!["https://example.com/acme"]);
+        assert_eq!(strings("$['dotted.key']", &root), vec!["found"]);
+        assert_eq!(strings("$[\"dotted.key\"]", &root), vec!["found"]);
+        // a relative path is the same as one from the root
+        assert_eq!(strings("query.search[0].title", &root), strings("$.query.search[0].title", &root));
+    }
+
+    #[test]
+    fn indexes() {
+        let root = response();
+        assert_eq!(strings("$.query.search[1].title", &root), vec!["Wile E. Coyote"]);
+        assert_eq!(strings("$.query.search[-1].title", &root), vec!["Road Runner"]);
+        assert!(strings("$.query.search[3].title", &root).is_empty());
+        assert!(strings("$.query.search[-4].title", &root).is_empty());
+        // an index into an object doesn't find anything
+        assert!(strings("$.query[0]", &root).is_empty());
+    }
+
+    #[test]
+    fn wildcards_and_descendants() {
+        let root = response();
+        assert_eq!(strings("$.query.search[*].pageid", &root), vec!["101", "102", "103"]);
+        assert_eq!(strings("$.query.search.*.title", &root).len(), 3);
+        // serde_json keeps an object's keys sorted, so "hits" comes before "query"
+        assert_eq!(strings("$..url", &root), vec!["https://example.com/acme", "https://example.com/road-runner"]);
+        assert_eq!(strings("$.query..title", &root), vec!["Acme Corporation", "Wile E. Coyote", "Road Runner"]);
+        // the array, its object, "_source" and "url"
+        assert_eq!(JsonPath::parse("$.hits..*").unwrap().select(&root).len(), 4);
+    }
+
+    #[test]
+    fn missing_paths_find_nothing() {
+        let root = response();
+        assert!(strings("$.nothing.here", &root).is_empty());
+        assert!(strings("$.query.search[*].url.more", &root).is_empty());
+        assert_eq!(JsonPath::parse("$.query.search[*].url").unwrap().select_string(&root),
+                   Some("https://example.com/road-runner".to_string()));
+        assert_eq!(JsonPath::parse("$.query").unwrap().select_string(&root), None);
+    }
+
+    #[test]
+    fn values_as_strings() {
+        let root = response();
+        let path = JsonPath::parse("$.query.search[0].featured").unwrap();
+        assert_eq!(path.select_string(&root), Some("true".to_string()));
+        assert_eq!(path.to_string(), "$.query.search[0].featured");
+    }
+
+    #[test]
+    fn bad_paths_are_rejected() {
+        assert!(JsonPath::parse("$.").is_err());
+        assert!(JsonPath::parse("$..").is_err());
+        assert!(JsonPath::parse("$.results[0").is_err());
+        assert!(JsonPath::parse("$.results[first]").is_err());
+        assert!(JsonPath::parse("$results").is_err());
+    }
+}
@@ -9,18 +9,32 @@ extern crate url;
 extern crate clap;
 
 pub mod configuration;
+mod blacklist;
+mod client;
+mod dedup;
 mod engine;
+mod entities;
 mod spider;
 mod fetcher;
+mod jsonpath;
+mod mangler;
+mod numbers;
 mod parser;
+mod phrases;
+mod politeness;
+mod profile;
+mod ranking;
+mod sources;
 mod finalizer;
 
 use std::thread;
 use std::sync::{Arc, Mutex};
-use clap::{App, Arg, ArgGroup};
+use clap::{App, AppSettings, Arg, ArgGroup, SubCommand};
 use config::Config;
 use std::fs::File;
 use std::io::{BufReader, BufRead};
+use std::collections::{HashMap, HashSet};
+use std::process;
 
 fn main() {
     println!("-=<|[[[ HEATED WORDS STARTED ]]]|>=-");
@@ -30,9 +44,16 @@ fn main() {
     let arguments =
         App::new("Heated Words")
             .arg(Arg::with_name("QUERY MODE")
+                .multiple(true)
+                .number_of_values(1)
                 .long("query")
                 .value_name("QUERY")
-                .help("The phrase that you want to search online")
+                .help("The phrase that you want to search online. Can be used multiple times!")
+            )
+            .arg(Arg::with_name("QUERY FILE MODE")
+                .long("query-file")
+                .value_name("QUERY_FILE")
+                .help("A file with one phrase to search online per line. Can be combined with --query")
             )
             .arg(Arg::with_name("NO ENGINE MODE")
                 .long("no-engines")
@@ -42,7 +63,13 @@ fn main() {
             )
             .group(ArgGroup::with_name("RUN MODES")
                 .required(true)
-                .args(&["QUERY MODE", "NO ENGINE MODE"])
+                .multiple(true)
+                .args(&["QUERY MODE", "QUERY FILE MODE", "NO ENGINE MODE"])
+            )
+            .arg(Arg::with_name("engine results only")
+                .long("engine-results-only")
+                .help("Build the wordlist from the titles and snippets on the engines' results pages \
+                without fetching the pages themselves")
             )
             .arg(Arg::with_name("configuration file")
                 .short("c")
@@ -58,6 +85,10 @@ fn main() {
                 .value_name("FILENAME")
                 .help("Sets a custom name for the wordlist output file.")
             )
+            .arg(Arg::with_name("wordlist per query")
+                .long("wordlist-per-query")
+                .help("Also write a wordlist for each query, from only the pages that query found")
+            )
             .arg(Arg::with_name("link list filename")
                 .long("links-output")
                 .value_name("FILENAME")
@@ -104,6 +135,89 @@ fn main() {
                 .help("Specify the maximum ratio of words in the URL (minus domain) \
                 that don't match any word in the query")
             )
+            .arg(Arg::with_name("crawl depth")
+                .long("crawl-depth")
+                .value_name("DEPTH")
+                .help("Follow links found on each fetched page up to DEPTH links away. \
+                0 disables crawling.")
+            )
+            .arg(Arg::with_name("expansion depth")
+                .long("expand-depth")
+                .value_name("DEPTH")
+                .help("Run the engines' search suggestions for each query as extra queries, and their \
+                suggestions in turn, up to DEPTH rounds. 0 disables expansion.")
+            )
+            .arg(Arg::with_name("expansion fan out")
+                .long("expand-fan-out")
+                .value_name("COUNT")
+                .help("Specify how many of each engine's suggestions for a query are run as extra queries")
+            )
+            .arg(Arg::with_name("crawl scope")
+                .long("crawl-scope")
+                .value_name("SCOPE")
+                .possible_values(&["domain", "path", "any"])
+                .help("Which crawled links to follow: those on the same domain, \
+                under the same path, or any link at all")
+            )
+            .arg(Arg::with_name("max pages")
+                .long("max-pages")
+                .value_name("COUNT")
+                .help("Stop crawling once this many pages have been fetched in total. \
+                -1 is unlimited.")
+            )
+            .arg(Arg::with_name("workers")
+                .short("t")
+                .long("threads")
+                .value_name("COUNT")
+                .help("Specify how many pages the Spider will fetch at the same time")
+            )
+            .arg(Arg::with_name("rule files")
+                .multiple(true)
+                .long("rules")
+                .value_name("RULE_FILE")
+                .help("Mangle the wordlist with the rules in a hashcat rule file. \
+                Can be used multiple times!")
+            )
+            .arg(Arg::with_name("profile")
+                .long("profile")
+                .value_name("PROFILE.toml")
+                .help("A file with the target's names, birthdates, and other details to build \
+                candidates from. See \"profile.example.toml\"")
+            )
+            .setting(AppSettings::SubcommandsNegateReqs)
+            .subcommand(SubCommand::with_name("blacklist")
+                .about("Tools for the blacklists that are removed from the wordlist")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("build")
+                    .about("Runs unrelated control queries through the engines and writes \
+                    the words their results have in common to a blacklist for each engine")
+                    .arg(Arg::with_name("engines")
+                        .multiple(true)
+                        .long("engine")
+                        .value_name("NAME")
+                        .help("Only build a blacklist for this engine (the key of its table in the \
+                        configuration). Can be used multiple times!")
+                    )
+                    .arg(Arg::with_name("query file")
+                        .long("queries")
+                        .value_name("QUERY_FILE")
+                        .help("A file with one control query per line to use instead of the ones \
+                        in the configuration")
+                    )
+                    .arg(Arg::with_name("min ratio")
+                        .long("min-ratio")
+                        .value_name("RATIO")
+                        .help("Specify the minimum ratio of control queries a word must be found \
+                        with to be blacklisted")
+                    )
+                    .arg(Arg::with_name("output directory")
+                        .long("output-dir")
+                        .value_name("DIRECTORY")
+                        .help("Where the \"<engine>_blacklist.txt\" files are written. \
+                        Defaults to \"./blacklists/\"")
+                    )
+                )
+            )
             .get_matches();
 
     // loading configuration file
@@ -117,11 +231,47 @@ fn main() {
     println!("###  Building configuration from arguments");
     let mut arg_config = Config::new();
 
-    let search_query = arguments.value_of("QUERY MODE").unwrap_or("");
-    arg_config.set("query", search_query).unwrap();
+    // every query from the command line and the query file, in order and without repeats
+    let mut queries: Vec<String> = match arguments.values_of("QUERY MODE") {
+        Some(values) => values.map(|query| query.to_string()).collect(),
+        None => Vec::new()
+    };
+    if let Some(filename) = arguments.value_of("QUERY FILE MODE") {
+        match File::open(filename) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line.unwrap();
+                    let query = line.trim();
+                    if !query.is_empty() && !query.starts_with('#') {
+                        queries.push(query.to_string());
+                    }
+                }
+            }
+            Err(e) => panic!("Query file cannot be opened: {}", e)
+        }
+        // otherwise the run would have nothing to do and wouldn't say why
+        if queries.is_empty() {
+            println!("!!!  Query file \"{}\" contains no queries", filename);
+            process::exit(1);
+        }
+    }
+    let mut seen_queries = HashSet::new();
+    queries.retain(|query| seen_queries.insert(query.clone()));
 
     let url_list_filename = arguments.value_of("NO ENGINE MODE").unwrap_or("");
+    // clap can't make an arg conflict with the others in a "multiple" group, so it's checked here
+    if !url_list_filename.is_empty() && !queries.is_empty() {
+        println!("!!!  --no-engines can't be used with --query or --query-file");
+        return;
+    }
+    if !url_list_filename.is_empty() && arguments.is_present("engine results only") {
+        println!("!!!  --engine-results-only needs --query or --query-file, there are no engine results with --no-engines");
+        return;
+    }
 
+    if arguments.is_present("engine results only") {
+        arg_config.set("engine_results.only", true).unwrap();
+    }
     if arguments.is_present("debug") {
         arg_config.set("debug", true).unwrap();
     }
@@ -131,6 +281,9 @@ fn main() {
         }
         None => ()
     }
+    if arguments.is_present("wordlist per query") {
+        arg_config.set("filenames.wordlist_per_query", true).unwrap();
+    }
     match arguments.value_of("link list filename") {
         Some(filename) => {
             arg_config.set("filenames.links", filename).unwrap();
@@ -198,6 +351,89 @@ fn main() {
         None => ()
     }
 
+    if let Some(value) = arguments.value_of("crawl depth") {
+        match value.parse::<i64>() {
+            Ok(depth) => {
+                arg_config.set("crawl.max_depth", depth).unwrap();
+            }
+            Err(e) => println!("!!!  Crawl depth is an invalid integer.\
+                Program will fall back to config file: {}", e)
+        }
+    }
+    if let Some(value) = arguments.value_of("expansion depth") {
+        match value.parse::<i64>() {
+            Ok(depth) => {
+                arg_config.set("expansion.max_depth", depth).unwrap();
+            }
+            Err(e) => println!("!!!  Expansion depth is an invalid integer.\
+                Program will fall back to config file: {}", e)
+        }
+    }
+    if let Some(value) = arguments.value_of("expansion fan out") {
+        match value.parse::<i64>() {
+            Ok(count) => {
+                arg_config.set("expansion.fan_out", count).unwrap();
+            }
+            Err(e) => println!("!!!  Expansion fan out is an invalid integer.\
+                Program will fall back to config file: {}", e)
+        }
+    }
+    if let Some(scope) = arguments.value_of("crawl scope") {
+        arg_config.set("crawl.scope", scope).unwrap();
+    }
+    if let Some(value) = arguments.value_of("max pages") {
+        match value.parse::<i64>() {
+            Ok(count) => {
+                arg_config.set("crawl.max_pages", count).unwrap();
+            }
+            Err(e) => println!("!!!  Max pages is an invalid integer.\
+                Program will fall back to config file: {}", e)
+        }
+    }
+
+    if let Some(value) = arguments.value_of("workers") {
+        match value.parse::<i64>() {
+            Ok(count) => {
+                arg_config.set("spider.workers", count).unwrap();
+            }
+            Err(e) => println!("!!!  Thread count is an invalid integer.\
+                Program will fall back to config file: {}", e)
+        }
+    }
+
+    if let Some(rule_files) = arguments.values_of("rule files") {
+        arg_config.set("mangling.rule_files", rule_files.collect::<Vec<&str>>()).unwrap();
+    }
+
+    if let Some(filename) = arguments.value_of("profile") {
+        arg_config.set("profile.file", filename).unwrap();
+    }
+
+    let build_arguments = arguments.subcommand_matches("blacklist")
+        .and_then(|blacklist_arguments| blacklist_arguments.subcommand_matches("build"));
+    if let Some(build_arguments) = build_arguments {
+        // only the result pages themselves are wanted, not what they link to
+        arg_config.set("crawl.max_depth", 0).unwrap();
+        if let Some(engines) = build_arguments.values_of("engines") {
+            arg_config.set("blacklist_builder.engines", engines.collect::<Vec<&str>>()).unwrap();
+        }
+        if let Some(filename) = build_arguments.value_of("query file") {
+            arg_config.set("blacklist_builder.query_file", filename).unwrap();
+        }
+        if let Some(value) = build_arguments.value_of("min ratio") {
+            match value.parse::<f64>() {
+                Ok(ratio) => {
+                    arg_config.set("blacklist_builder.min_query_ratio", ratio).unwrap();
+                }
+                Err(e) => println!("!!!  Minimum query ratio is an invalid float.\
+                    Program will fall back to config file: {}", e)
+            }
+        }
+        if let Some(directory) = build_arguments.value_of("output directory") {
+            arg_config.set("blacklist_builder.output_directory", directory).unwrap();
+        }
+    }
+
     // joining arguments "Config" object with the config file. The arguments override the file
     println!("###  Joining arguments to config file for global access");
     match configuration::CONFIGURATION.write() {
@@ -211,13 +447,32 @@ fn main() {
     }
 
 
-    // create Vector with thread safety to hold list of URLs to retrieve
+    if build_arguments.is_some() {
+        blacklist::build_from_engines();
+        println!("--=<{{[[[  HEATED WORDS COMPLETED  ]]]}}>=--");
+        return;
+    }
+
+    // create Vector with thread safety to hold list of URLs to retrieve, along with the engine that found them
     let complete_link_list = Arc::new(Mutex::new(Vec::new()));
+    // and which queries found each of them, so the same link found by several queries is only fetched once
+    let link_queries: Arc<Mutex<HashMap<String, Vec<String>>>> = Arc::new(Mutex::new(HashMap::new()));
+    // and the words in the engines' titles and snippets for them
+    let snippet_pages: Arc<Mutex<Vec<spider::PageWords>>> = Arc::new(Mutex::new(Vec::new()));
+    let (harvest_snippets, engine_results_only) = match configuration::CONFIGURATION.read() {
+        Ok(config) => (
+            config.get_bool("engine_results.words").unwrap_or(true),
+            config.get_bool("engine_results.only").unwrap_or(false)
+        ),
+        Err(e) => panic!("Couldn't read configuration: {}", e)
+    };
 
     //run QUERY mode if specified
-    if arguments.is_present("QUERY MODE") {
-        // debug print query
-        println!("###  Running program with query: \"{}\"", search_query);
+    if !queries.is_empty() {
+        // debug print queries
+        for query in &queries {
+            println!("###  Running program with query: \"{}\"", query);
+        }
 
         //build search engines from config file that we loaded earlier
         let engines = engine::build_engines();
@@ -227,6 +482,9 @@ fn main() {
             println!("~~~   {}", &engine);
         }
 
+        // add the engines' related searches for the queries, when expansion is turned on
+        queries = engine::expand_queries(&engines, &queries);
+
 
         // create Vector of engine threads to join later
         let mut running_engines = Vec::new();
@@ -235,15 +493,50 @@ fn main() {
         println!("###  Dispatching engines...");
         for engine in engines {
             let shared_list = Arc::clone(&complete_link_list);
+            let shared_queries = Arc::clone(&link_queries);
+            let shared_snippets = Arc::clone(&snippet_pages);
+            let engine_queries = queries.clone();
             running_engines.push(thread::spawn(move || {
-                match engine.dispatch() {
-                    Ok(links) => {
-                        let mut link_vector = shared_list.lock().unwrap();
-                        for link in links {
-                            link_vector.push(link);
+                // the engine's titles and snippets for each link, the first time it showed the link
+                let mut described = HashSet::new();
+                for (query, result) in engine.dispatch(&engine_queries) {
+                    match result {
+                        Ok(results) => {
+                            if harvest_snippets {
+                                let mut snippet_vector = shared_snippets.lock().unwrap();
+                                for result in &results {
+                                    let words = result.words();
+                                    if !words.is_empty() && described.insert(result.link.clone()) {
+                                        snippet_vector.push(spider::PageWords {
+                                            url: result.link.clone(),
+                                            seed: result.link.clone(),
+                                            engine: Some(engine.name().to_string()),
+                                            words,
+                                        });
+                                    }
+                                }
+                            }
+
+                            let mut link_vector = shared_list.lock().unwrap();
+                            let mut found_with = shared_queries.lock().unwrap();
+                            for link in results.into_iter().map(|result| result.link) {
+                                // links are merged across queries and engines, the first engine to find one keeps it
+                                match found_with.get_mut(&link) {
+                                    Some(link_queries) => {
+                                        if !link_queries.contains(&query) {
+                                            link_queries.push(query.clone());
+                                        }
+                                    }
+                                    None => {
+                                        found_with.insert(link.clone(), vec![query.clone()]);
+                                        // remember which engine found each link so blacklists can be scoped to it
+                                        link_vector.push((link, Some(engine.name().to_string())));
+                                    }
+                                }
+                            }
                         }
+                        Err(e) => println!("!!!  {} failed on query \"{}\": {}", engine, query, e)
                     }
-                    Err(e) => println!("{}", e)
                 }
             }));
         }
@@ -269,8 +562,11 @@ fn main() {
         };
         let url_reader = BufReader::new(input_file);
         for line in url_reader.lines() {
-            let url = line.unwrap();
-            link_vector.push(url);
+            // only the first column is used, so a previous run's link list can be fed back in
+            let line = line.unwrap();
+            if let Some(url) = line.split_whitespace().next() {
+                link_vector.push((url.to_string(), None));
+            }
         }
     } else {
         panic!("NO RUNNING MODE SPECIFIED"); //this should be unreachable, but just to make sure :)
@@ -282,27 +578,46 @@ fn main() {
         Ok(list) => {
             println!("$$$  Collected {} links in total", list.len());
             if configuration::read_debug() {
-                for link in list.iter() {
+                for (link, _) in list.iter() {
                     println!("L: {}", link);
                 }
             }
 
-            // create Spider with a list of URLs which it needs to retrieve
-            let mut spider = spider::Spider::new(list.to_vec());
-            println!("###  Dispatching spider...");
-            // dispatch hands execution of program to spider which will eventually return a Vec<String> of each word the fetchers retrieved
-            let mut results: Vec<String> = spider.dispatch();
+            let mut results: Vec<spider::PageWords> = snippet_pages.lock().unwrap().drain(..).collect();
+            println!("$$$  Collected words from {} engine result snippets", results.len());
 
+            let spider = if engine_results_only {
+                println!("###  Building the wordlist from the engine results alone, the pages won't be fetched");
+                println!("###  Finalizing results");
+                finalizer::finish_link_vector(list.iter().map(|(link, _)| (link.clone(), None)).collect());
+                None
+            } else {
+                // create Spider with a list of URLs which it needs to retrieve
+                let mut spider = spider::Spider::new(list.to_vec());
+                println!("###  Dispatching spider...");
+                // dispatch hands execution of program to spider which will eventually return the words each page produced
+                results.extend(spider.dispatch());
 
-            println!("###  Finalizing results");
-            println!("~~~  finalizing link list of length {}", list.to_vec().len());
-            finalizer::finish_link_vector(list.to_vec());
-            println!("~~~  finalizing wordlist of length {}", results.len());
-            finalizer::finish_wordlist(&mut results);
+
+                println!("###  Finalizing results");
+                let fetched_links = spider.fetched_links();
+                println!("~~~  finalizing link list of length {}", fetched_links.len());
+                finalizer::finish_link_vector(fetched_links);
+                Some(spider)
+            };
+            println!("~~~  finalizing wordlist from {} pages", results.len());
+            if configuration::CONFIGURATION.read().unwrap().get_bool("filenames.wordlist_per_query").unwrap_or(false) {
+                finalizer::finish_query_wordlists(&results, &queries, &link_queries.lock().unwrap());
+            }
+            finalizer::finish_wordlist(results);
 
             println!("###  Executing post-processing");
             finalizer::run_post_processing();  // Run scripts/commands/etc as specified in configuration file
 
+            if let Some(spider) = spider {
+                spider.report_failures();
+            }
+
             println!("--=<{{[[[  HEATED WORDS COMPLETED  ]]]}}>=--");
         }
         Err(e) => panic!("{}", e)
@@ -0,0 +1,783 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufRead};
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::configuration;
+use super::dedup;
+
+// hashcat won't produce anything longer than this, and neither will we
+const MAX_MANGLED_LENGTH: usize = 256;
+
+// one function of a hashcat rule, see https://hashcat.net/wiki/doku.php?id=rule_based_attack
+// positions are in characters and anything out of range leaves the word unchanged, like hashcat
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Nothing,  // :
+    Lowercase,  // l
+    Uppercase,  // u
+    Capitalize,  // c
+    InvertCapitalize,  // C
+    ToggleCase,  // t
+    ToggleAt(usize),  // TN
+    Reverse,  // r
+    Duplicate,  // d
+    DuplicateTimes(usize),  // pN
+    Reflect,  // f
+    RotateLeft,  // {
+    RotateRight,  // }
+    Append(char),  // $X
+    Prepend(char),  // ^X
+    DeleteFirst,  // [
+    DeleteLast,  // ]
+    DeleteAt(usize),  // DN
+    Extract(usize, usize),  // xNM
+    Omit(usize, usize),  // ONM
+    Insert(usize, char),  // iNX
+    Overwrite(usize, char),  // oNX
+    Truncate(usize),  // 'N
+    Replace(char, char),  // sXY
+    Purge(char),  // @X
+    DuplicateFirst(usize),  // zN
+    DuplicateLast(usize),  // ZN
+    DuplicateEach,  // q
+    DuplicateBlockFront(usize),  // yN
+    DuplicateBlockBack(usize),  // YN
+    SwapFront,  // k
+    SwapBack,  // K
+    Swap(usize, usize),  // *NM
+    Increment(usize),  // +N
+    Decrement(usize),  // -N
+    ReplaceNext(usize),  // .N
+    ReplacePrevious(usize),  // ,N
+    TitleCase,  // E
+}
+
+// a line of a rule file, every function of it applied in order
+#[derive(Clone, Debug)]
+pub struct Rule {
+    text: String,
+    functions: Vec<Function>,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Rule \"{}\"", self.text)
+    }
+}
+
+impl Rule {
+    pub fn parse(text: &str) -> Result<Rule, String> {
+        let mut functions = Vec::new();
+        let mut characters = text.chars();
+        while let Some(name) = characters.next() {
+            let function = match name {
+                // spaces between functions are allowed (but "$ " still appends a space)
+                ' ' | '\t' => continue,
+                ':' => Function::Nothing,
+                'l' => Function::Lowercase,
+                'u' => Function::Uppercase,
+                'c' => Function::Capitalize,
+                'C' => Function::InvertCapitalize,
+                't' => Function::ToggleCase,
+                'T' => Function::ToggleAt(read_position(&mut characters, name)?),
+                'r' => Function::Reverse,
+                'd' => Function::Duplicate,
+                'p' => Function::DuplicateTimes(read_position(&mut characters, name)?),
+                'f' => Function::Reflect,
+                '{' => Function::RotateLeft,
+                '}' => Function::RotateRight,
+                '$' => Function::Append(read_character(&mut characters, name)?),
+                '^' => Function::Prepend(read_character(&mut characters, name)?),
+                '[' => Function::DeleteFirst,
+                ']' => Function::DeleteLast,
+                'D' => Function::DeleteAt(read_position(&mut characters, name)?),
+                'x' => Function::Extract(read_position(&mut characters, name)?, read_position(&mut characters, name)?),
+                'O' => Function::Omit(read_position(&mut characters, name)?, read_position(&mut characters, name)?),
+                'i' => Function::Insert(read_position(&mut characters, name)?, read_character(&mut characters, name)?),
+                'o' => Function::Overwrite(read_position(&mut characters, name)?, read_character(&mut characters, name)?),
+                '\'' => Function::Truncate(read_position(&mut characters, name)?),
+                's' => Function::Replace(read_character(&mut characters, name)?, read_character(&mut characters, name)?),
+                '@' => Function::Purge(read_character(&mut characters, name)?),
+                'z' => Function::DuplicateFirst(read_position(&mut characters, name)?),
+                'Z' => Function::DuplicateLast(read_position(&mut characters, name)?),
+                'q' => Function::DuplicateEach,
+                'y' => Function::DuplicateBlockFront(read_position(&mut characters, name)?),
+                'Y' => Function::DuplicateBlockBack(read_position(&mut characters, name)?),
+                'k' => Function::SwapFront,
+                'K' => Function::SwapBack,
+                '*' => Function::Swap(read_position(&mut characters, name)?, read_position(&mut characters, name)?),
+                '+' => Function::Increment(read_position(&mut characters, name)?),
+                '-' => Function::Decrement(read_position(&mut characters, name)?),
+                '.' => Function::ReplaceNext(read_position(&mut characters, name)?),
+                ',' => Function::ReplacePrevious(read_position(&mut characters, name)?),
+                'E' => Function::TitleCase,
+                other => return Err(format!("unsupported function '{}'", other))
+            };
+            functions.push(function);
+        }
+        if functions.is_empty() {
+            return Err(String::from("the rule is empty"));
+        }
+
+        Ok(Rule {
+            text: text.to_string(),
+            functions,
+        })
+    }
+
+    // the mangled word, or None if there's nothing left of it or it got too long
+    pub fn apply(&self, word: &str) -> Option<String> {
+        let mut characters: Vec<char> = word.chars().collect();
+        for function in &self.functions {
+            characters = apply_function(*function, characters);
+            if characters.len() > MAX_MANGLED_LENGTH {
+                return None;
+            }
+        }
+        if characters.is_empty() {
+            None
+        } else {
+            Some(characters.into_iter().collect())
+        }
+    }
+}
+
+// positions are 0-9 then A-Z for 10-35
+fn read_position(characters: &mut std::str::Chars, name: char) -> Result<usize, String> {
+    match characters.next() {
+        Some(position @ '0'..='9') => Ok(position as usize - '0' as usize),
+        Some(position @ 'A'..='Z') => Ok(position as usize - 'A' as usize + 10),
+        Some(other) => Err(format!("'{}' isn't a valid position for function '{}'", other, name)),
+        None => Err(format!("function '{}' is missing a position", name))
+    }
+}
+
+fn read_character(characters: &mut std::str::Chars, name: char) -> Result<char, String> {
+    characters.next().ok_or_else(|| format!("function '{}' is missing a character", name))
+}
+
+fn toggle(character: char) -> Vec<char> {
+    if character.is_lowercase() {
+        character.to_uppercase().collect()
+    } else if character.is_uppercase() {
+        character.to_lowercase().collect()
+    } else {
+        vec![character]
+    }
+}
+
+fn lowercase(characters: &[char]) -> Vec<char> {
+    characters.iter().flat_map(|character| character.to_lowercase()).collect()
+}
+
+fn uppercase(characters: &[char]) -> Vec<char> {
+    characters.iter().flat_map(|character| character.to_uppercase()).collect()
+}
+
+fn apply_function(function: Function, mut word: Vec<char>) -> Vec<char> {
+    let length = word.len();
+    match function {
+        Function::Nothing => word,
+        Function::Lowercase => lowercase(&word),
+        Function::Uppercase => uppercase(&word),
+        Function::Capitalize => match word.split_first() {
+            Some((first, rest)) => uppercase(&[*first]).into_iter().chain(lowercase(rest)).collect(),
+            None => word
+        },
+        Function::InvertCapitalize => match word.split_first() {
+            Some((first, rest)) => lowercase(&[*first]).into_iter().chain(uppercase(rest)).collect(),
+            None => word
+        },
+        Function::ToggleCase => word.into_iter().flat_map(toggle).collect(),
+        Function::ToggleAt(position) => {
+            if position < length {
+                let toggled = toggle(word[position]);
+                word.splice(position..=position, toggled);
+            }
+            word
+        }
+        Function::Reverse => {
+            word.reverse();
+            word
+        }
+        Function::Duplicate => word.repeat(2),
+        Function::DuplicateTimes(times) => word.repeat(times + 1),
+        Function::Reflect => {
+            let reversed: Vec<char> = word.iter().rev().cloned().collect();
+            word.extend(reversed);
+            word
+        }
+        Function::RotateLeft => {
+            if length > 0 {
+                word.rotate_left(1);
+            }
+            word
+        }
+        Function::RotateRight => {
+            if length > 0 {
+                word.rotate_right(1);
+            }
+            word
+        }
+        Function::Append(character) => {
+            word.push(character);
+            word
+        }
+        Function::Prepend(character) => {
+            word.insert(0, character);
+            word
+        }
+        Function::DeleteFirst => {
+            if length > 0 {
+                word.remove(0);
+            }
+            word
+        }
+        Function::DeleteLast => {
+            word.pop();
+            word
+        }
+        Function::DeleteAt(position) => {
+            if position < length {
+                word.remove(position);
+            }
+            word
+        }
+        Function::Extract(start, count) => {
+            if start < length {
+                word[start..(start + count).min(length)].to_vec()
+            } else {
+                word
+            }
+        }
+        Function::Omit(start, count) => {
+            if start < length {
+                word.drain(start..(start + count).min(length));
+            }
+            word
+        }
+        Function::Insert(position, character) => {
+            if position <= length {
+                word.insert(position, character);
+            }
+            word
+        }
+        Function::Overwrite(position, character) => {
+            if position < length {
+                word[position] = character;
+            }
+            word
+        }
+        Function::Truncate(position) => {
+            word.truncate(position);
+            word
+        }
+        Function::Replace(from, to) => word.into_iter().map(|character| if character == from { to } else { character }).collect(),
+        Function::Purge(purged) => word.into_iter().filter(|&character| character != purged).collect(),
+        Function::DuplicateFirst(times) => match word.first() {
+            Some(&first) => {
+                word.splice(0..0, vec![first; times]);
+                word
+            }
+            None => word
+        },
+        Function::DuplicateLast(times) => {
+            if let Some(&last) = word.last() {
+                word.resize(length + times, last);
+            }
+            word
+        }
+        Function::DuplicateEach => word.into_iter().flat_map(|character| vec![character, character]).collect(),
+        Function::DuplicateBlockFront(count) => {
+            if count <= length {
+                let block: Vec<char> = word[..count].to_vec();
+                word.splice(0..0, block);
+            }
+            word
+        }
+        Function::DuplicateBlockBack(count) => {
+            if count <= length {
+                let block: Vec<char> = word[length - count..].to_vec();
+                word.extend(block);
+            }
+            word
+        }
+        Function::SwapFront => {
+            if length >= 2 {
+                word.swap(0, 1);
+            }
+            word
+        }
+        Function::SwapBack => {
+            if length >= 2 {
+                word.swap(length - 1, length - 2);
+            }
+            word
+        }
+        Function::Swap(first, second) => {
+            if first < length && second < length {
+                word.swap(first, second);
+            }
+            word
+        }
+        Function::Increment(position) => {
+            if position < length {
+                word[position] = std::char::from_u32(word[position] as u32 + 1).unwrap_or(word[position]);
+            }
+            word
+        }
+        Function::Decrement(position) => {
+            if position < length && word[position] as u32 > 0 {
+                word[position] = std::char::from_u32(word[position] as u32 - 1).unwrap_or(word[position]);
+            }
+            word
+        }
+        Function::ReplaceNext(position) => {
+            if position + 1 < length {
+                word[position] = word[position + 1];
+            }
+            word
+        }
+        Function::ReplacePrevious(position) => {
+            if position > 0 && position < length {
+                word[position] = word[position - 1];
+            }
+            word
+        }
+        Function::TitleCase => {
+            let mut result = Vec::with_capacity(length);
+            let mut after_space = true;
+            for character in word {
+                if after_space {
+                    result.extend(character.to_uppercase());
+                } else {
+                    result.extend(character.to_lowercase());
+                }
+                after_space = character == ' ';
+            }
+            result
+        }
+    }
+}
+
+// every rule in a hashcat rule file, skipping comments and the rules we can't apply
+pub fn read_rule_file(filename: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    match File::open(filename) {
+        Ok(file) => {
+            for (number, line) in BufReader::new(file).lines().enumerate() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        println!("!!!  Rule file \"{}\" could not be read: {}", filename, e);
+                        break;
+                    }
+                };
+                // "#" only starts a comment at the beginning of a line, since it's a valid argument anywhere else
+                if line.trim().is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                match Rule::parse(line.trim_end_matches('\r')) {
+                    Ok(rule) => rules.push(rule),
+                    Err(e) => println!("!!!  Skipping rule on line {} of \"{}\": {}", number + 1, filename, e)
+                }
+            }
+        }
+        Err(e) => println!("!!!  Rule file \"{}\" could not be opened: {}", filename, e)
+    }
+    rules
+}
+
+// every rule from every file in "mangling.rule_files", in order
+pub fn read_rules() -> Vec<Rule> {
+    let filenames = match configuration::CONFIGURATION.read().unwrap().get_array("mangling.rule_files") {
+        Ok(values) => values.into_iter()
+            .filter_map(|value| match value.into_str() {
+                Ok(filename) => Some(filename),
+                Err(e) => {
+                    println!("!!!  Rule file name isn't a valid string: {}", e);
+                    None
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new()
+    };
+
+    let mut rules = Vec::new();
+    for filename in filenames {
+        let file_rules = read_rule_file(filename.as_str());
+        println!("###  Loaded {} rules from \"{}\"", file_rules.len(), filename);
+        rules.extend(file_rules);
+    }
+    rules
+}
+
+// the words the presets are applied to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresetScope {
+    Critical,  // capitalized words and phrases
+    All,
+}
+
+// the named presets in "mangling.presets", each one a set of rules, applied one after another
+#[derive(Clone, Debug)]
+pub struct Presets {
+    chain: Vec<(String, Vec<Rule>)>,
+    scope: PresetScope,
+    max_variants_per_word: usize,
+    max_total_words: Option<usize>,
+}
+
+impl Presets {
+    // every variant of a word, the word itself first, until the per-word budget runs out
+    fn variants(&self, word: &str) -> Vec<String> {
+        let mut variants = vec![word.to_string()];
+        for (_, rules) in &self.chain {
+            let mut next = Vec::new();
+            let mut seen = HashSet::new();
+            // each preset builds on the variants of the ones before it (e.g. "Rockets" then "Rockets2020"),
+            // one rule at a time over all of them so the budget doesn't go to the first variant's variants
+            'variants: for rule in rules {
+                for variant in &variants {
+                    if let Some(mangled) = rule.apply(variant) {
+                        if seen.insert(mangled.clone()) {
+                            next.push(mangled);
+                            if next.len() >= self.max_variants_per_word {
+                                break 'variants;
+                            }
+                        }
+                    }
+                }
+            }
+            variants = next;
+        }
+        variants
+    }
+}
+
+// every rule applied to every word, and the presets to the words in their scope (the critical words are case-folded),
+// keeping each word's results together so the best words stay at the top
+pub fn mangle(words: &[String], rules: &[Rule], presets: Option<&Presets>, critical_words: &HashSet<String>) -> Vec<String> {
+    let mut result = Vec::with_capacity(words.len() * rules.len().max(1));
+    let mut over_budget = false;
+    for word in words {
+        let mut variants = Vec::new();
+        if let Some(presets) = presets {
+            let in_scope = presets.scope == PresetScope::All || critical_words.contains(&dedup::fold_case(word));
+            let in_budget = match presets.max_total_words {
+                Some(max) => result.len() < max,
+                None => true
+            };
+            if in_scope && in_budget {
+                variants.extend(presets.variants(word));
+            } else {
+                if in_scope && !over_budget {
+                    println!("!!!  The mangling budget of {} words ran out, the rest of the words won't get the presets",
+                             result.len());
+                    over_budget = true;
+                }
+                variants.push(word.clone());
+            }
+        }
+        for rule in rules {
+            if let Some(mangled) = rule.apply(word) {
+                variants.push(mangled);
+            }
+        }
+        result.extend(variants);
+    }
+    result
+}
+
+fn leet_rules(substitutions: &[(char, Vec<char>)]) -> Vec<Rule> {
+    // every combination of substituting (or not) each letter, both cases, with the word unchanged first
+    let mut rules = vec![String::new()];
+    for (letter, replacements) in substitutions {
+        let mut next = Vec::new();
+        for rule in &rules {
+            next.push(rule.clone());
+            for replacement in replacements {
+                let mut substituted = format!("{} s{}{}", rule, letter.to_lowercase(), replacement);
+                if letter.is_alphabetic() {
+                    substituted.push_str(format!(" s{}{}", letter.to_uppercase(), replacement).as_str());
+                }
+                next.push(substituted);
+            }
+        }
+        rules = next;
+    }
+    rules.iter().map(|rule| Rule::parse(format!(":{}", rule).as_str()).unwrap()).collect()
+}
+
+fn case_rules() -> Vec<Rule> {
+    // lower, Upper, UPPER, tOGGLE
+    [":", "l", "c", "u", "C"].iter().map(|rule| Rule::parse(rule).unwrap()).collect()
+}
+
+fn suffix_rules(suffixes: &[String]) -> Vec<Rule> {
+    let mut rules = vec![Rule::parse(":").unwrap()];
+    for suffix in suffixes {
+        let rule: String = suffix.chars().map(|character| format!("${}", character)).collect();
+        if let Ok(rule) = Rule::parse(rule.as_str()) {
+            rules.push(rule);
+        }
+    }
+    rules
+}
+
+fn read_string_list(config: &config::Config, key: &str, default: &[&str]) -> Vec<String> {
+    match config.get_array(key) {
+        Ok(values) => values.into_iter()
+            .filter_map(|value| match value.into_str() {
+                Ok(string) => Some(string),
+                Err(e) => {
+                    println!("!!!  An entry of \"{}\" isn't a valid string: {}", key, e);
+                    None
+                }
+            })
+            .collect(),
+        Err(_) => default.iter().map(|string| string.to_string()).collect()
+    }
+}
+
+fn read_leet_substitutions(config: &config::Config) -> Vec<(char, Vec<char>)> {
+    match config.get_table("mangling.leet") {
+        Ok(table) => {
+            let mut substitutions: Vec<(char, Vec<char>)> = table.into_iter()
+                .filter_map(|(letter, replacements)| {
+                    let mut characters = letter.chars();
+                    let letter = match (characters.next(), characters.next()) {
+                        (Some(character), None) => character,
+                        _ => {
+                            println!("!!!  Leet substitutions are for single letters, \"{}\" will be ignored", letter);
+                            return None;
+                        }
+                    };
+                    match replacements.into_array() {
+                        Ok(replacements) => Some((letter, replacements.into_iter()
+                            .filter_map(|replacement| replacement.into_str().ok())
+                            .filter_map(|replacement| replacement.chars().next())
+                            .collect())),
+                        Err(e) => {
+                            println!("!!!  Leet substitutions for \"{}\" should be an array: {}", letter, e);
+                            None
+                        }
+                    }
+                })
+                .collect();
+            // tables don't keep their order, so sort them to get the same output every run
+            substitutions.sort();
+            substitutions
+        }
+        Err(_) => vec![('a', vec!['4', '@']), ('e', vec!['3']), ('s', vec!['$', '5'])]
+    }
+}
+
+fn read_years(config: &config::Config) -> Vec<String> {
+    // the current year, close enough for a default
+    let this_year = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => 1970 + (elapsed.as_secs() as f64 / 31_556_952.0) as i64,
+        Err(_) => 2020
+    };
+    let first = config.get_int("mangling.years.first").unwrap_or(this_year - 5);
+    let last = config.get_int("mangling.years.last").unwrap_or(this_year);
+    let two_digit = config.get_bool("mangling.years.two_digit").unwrap_or(false);
+    if first > last {
+        println!("!!!  \"mangling.years.first\" is after \"mangling.years.last\", no years will be added");
+    }
+
+    let mut years = Vec::new();
+    for year in first..=last {
+        years.push(year.to_string());
+        if two_digit {
+            years.push(format!("{:02}", year % 100));
+        }
+    }
+    years
+}
+
+// the presets in "mangling.presets", or None if there aren't any
+pub fn read_presets() -> Option<Presets> {
+    let config = configuration::CONFIGURATION.read().unwrap();
+
+    let mut chain = Vec::new();
+    for name in read_string_list(&config, "mangling.presets", &[]) {
+        let rules = match name.to_lowercase().as_str() {
+            "leet" => leet_rules(&read_leet_substitutions(&config)),
+            "case" => case_rules(),
+            "years" => suffix_rules(&read_years(&config)),
+            "specials" => suffix_rules(&read_string_list(&config, "mangling.specials.suffixes", &["!", "!!", "1!", "123"])),
+            other => {
+                println!("!!!  Unknown mangling preset \"{}\" will be ignored", other);
+                continue;
+            }
+        };
+        chain.push((name, rules));
+    }
+    if chain.is_empty() {
+        return None;
+    }
+
+    let scope = match config.get_str("mangling.preset_scope") {
+        Ok(value) => match value.to_lowercase().as_str() {
+            "critical" => PresetScope::Critical,
+            "all" => PresetScope::All,
+            other => {
+                println!("!!!  Unknown preset scope \"{}\". Defaulting to \"critical\"", other);
+                PresetScope::Critical
+            }
+        },
+        Err(_) => PresetScope::Critical
+    };
+    let max_variants_per_word = match config.get_int("mangling.max_variants_per_word") {
+        Ok(value) if value >= 1 => value as usize,
+        Ok(_) => {
+            println!("!!!  \"mangling.max_variants_per_word\" must be at least 1. Defaulting to 1");
+            1
+        }
+        Err(_) => 100
+    };
+    let max_total_words = match config.get_int("mangling.max_total_words") {
+        Ok(value) if value >= 0 => Some(value as usize),
+        _ => None
+    };
+
+    println!("###  Using mangling presets: {}",
+             chain.iter().map(|(name, rules)| format!("{} ({} rules)", name, rules.len())).collect::<Vec<String>>().join(", "));
+
+    Some(Presets {
+        chain,
+        scope,
+        max_variants_per_word,
+        max_total_words,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mangled(rule: &str, word: &str) -> Option<String> {
+        Rule::parse(rule).unwrap().apply(word)
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn case_functions() {
+        assert_eq!(mangled(":", "pAssWord"), Some("pAssWord".to_string()));
+        assert_eq!(mangled("l", "pAssWord"), Some("password".to_string()));
+        assert_eq!(mangled("u", "pAssWord"), Some("PASSWORD".to_string()));
+        assert_eq!(mangled("c", "pAssWord"), Some("Password".to_string()));
+        assert_eq!(mangled("C", "pAssWord"), Some("pASSWORD".to_string()));
+        assert_eq!(mangled("t", "pAssWord"), Some("PaSSwORD".to_string()));
+        assert_eq!(mangled("T0", "pAssWord"), Some("PAssWord".to_string()));
+        assert_eq!(mangled("TA", "pAssWord"), Some("pAssWord".to_string()));
+        assert_eq!(mangled("E", "pASS wORD"), Some("Pass Word".to_string()));
+    }
+
+    #[test]
+    fn order_functions() {
+        assert_eq!(mangled("r", "Pass"), Some("ssaP".to_string()));
+        assert_eq!(mangled("d", "Pass"), Some("PassPass".to_string()));
+        assert_eq!(mangled("p2", "Pass"), Some("PassPassPass".to_string()));
+        assert_eq!(mangled("f", "Pass"), Some("PassssaP".to_string()));
+        assert_eq!(mangled("{", "Pass"), Some("assP".to_string()));
+        assert_eq!(mangled("}", "Pass"), Some("sPas".to_string()));
+        assert_eq!(mangled("k", "Pass"), Some("aPss".to_string()));
+        assert_eq!(mangled("K", "Pasw"), Some("Paws".to_string()));
+        assert_eq!(mangled("*03", "Pasw"), Some("wasP".to_string()));
+        assert_eq!(mangled("*09", "Pasw"), Some("Pasw".to_string()));
+    }
+
+    #[test]
+    fn insert_and_delete_functions() {
+        assert_eq!(mangled("$1 $!", "Pass"), Some("Pass1!".to_string()));
+        assert_eq!(mangled("$ ", "Pass"), Some("Pass ".to_string()));
+        assert_eq!(mangled("^1", "Pass"), Some("1Pass".to_string()));
+        assert_eq!(mangled("[", "Pass"), Some("ass".to_string()));
+        assert_eq!(mangled("]", "Pass"), Some("Pas".to_string()));
+        assert_eq!(mangled("D1", "Pass"), Some("Pss".to_string()));
+        assert_eq!(mangled("D9", "Pass"), Some("Pass".to_string()));
+        assert_eq!(mangled("x12", "Password"), Some("as".to_string()));
+        assert_eq!(mangled("O12", "Password"), Some("Psword".to_string()));
+        assert_eq!(mangled("i4!", "Pass"), Some("Pass!".to_string()));
+        assert_eq!(mangled("i5!", "Pass"), Some("Pass".to_string()));
+        assert_eq!(mangled("o0p", "Pass"), Some("pass".to_string()));
+        assert_eq!(mangled("'2", "Pass"), Some("Pa".to_string()));
+        assert_eq!(mangled("sa@", "Banana"), Some("B@n@n@".to_string()));
+        assert_eq!(mangled("@a", "Banana"), Some("Bnn".to_string()));
+    }
+
+    #[test]
+    fn duplication_functions() {
+        assert_eq!(mangled("z2", "Pass"), Some("PPPass".to_string()));
+        assert_eq!(mangled("Z2", "Pass"), Some("Passss".to_string()));
+        assert_eq!(mangled("q", "Pas"), Some("PPaass".to_string()));
+        assert_eq!(mangled("y2", "Pass"), Some("PaPass".to_string()));
+        assert_eq!(mangled("Y2", "Pass"), Some("Passss".to_string()));
+        assert_eq!(mangled("y5", "Pass"), Some("Pass".to_string()));
+    }
+
+    #[test]
+    fn character_functions() {
+        assert_eq!(mangled("+0", "Pass"), Some("Qass".to_string()));
+        assert_eq!(mangled("-0", "Pass"), Some("Oass".to_string()));
+        assert_eq!(mangled(".0", "Pass"), Some("aass".to_string()));
+        assert_eq!(mangled(",1", "Pass"), Some("PPss".to_string()));
+        assert_eq!(mangled(",0", "Pass"), Some("Pass".to_string()));
+    }
+
+    #[test]
+    fn nothing_left_or_too_long_is_dropped() {
+        assert_eq!(mangled("[", "a"), None);
+        assert_eq!(mangled("'0", "Pass"), None);
+        assert_eq!(mangled("p9 p9 p9", "Password"), None);
+    }
+
+    #[test]
+    fn bad_rules_are_rejected() {
+        assert_eq!(Rule::parse("").unwrap_err(), "the rule is empty");
+        assert_eq!(Rule::parse("   ").unwrap_err(), "the rule is empty");
+        assert_eq!(Rule::parse("M").unwrap_err(), "unsupported function 'M'");
+        assert_eq!(Rule::parse("$").unwrap_err(), "function '$' is missing a character");
+        assert_eq!(Rule::parse("s1").unwrap_err(), "function 's' is missing a character");
+        assert_eq!(Rule::parse("T").unwrap_err(), "function 'T' is missing a position");
+        assert_eq!(Rule::parse("Ta").unwrap_err(), "'a' isn't a valid position for function 'T'");
+        assert_eq!(Rule::parse("x1").unwrap_err(), "function 'x' is missing a position");
+    }
+
+    #[test]
+    fn rules_keep_each_words_variants_together() {
+        let rules = vec![Rule::parse(":").unwrap(), Rule::parse("$1").unwrap()];
+        assert_eq!(mangle(&strings(&["Acme", "Rocket"]), &rules, None, &HashSet::new()),
+                   strings(&["Acme", "Acme1", "Rocket", "Rocket1"]));
+    }
+
+    #[test]
+    fn presets_build_on_each_other_within_their_scope_and_budget() {
+        let presets = Presets {
+            chain: vec![
+                ("case".to_string(), vec![Rule::parse(":").unwrap(), Rule::parse("u").unwrap()]),
+                ("years".to_string(), suffix_rules(&strings(&["2020"]))),
+            ],
+            scope: PresetScope::Critical,
+            max_variants_per_word: 3,
+            max_total_words: None,
+        };
+        let critical: HashSet<String> = vec!["acme".to_string()].into_iter().collect();
+        assert_eq!(mangle(&strings(&["Acme", "rocket"]), &[], Some(&presets), &critical),
+                   strings(&["Acme", "ACME", "Acme2020", "rocket"]));
+    }
+
+    #[test]
+    fn leet_substitutes_both_cases() {
+        let rules = leet_rules(&[('a', vec!['4'])]);
+        let variants: Vec<String> = rules.iter().filter_map(|rule| rule.apply("Banana Acme")).collect();
+        assert_eq!(variants, strings(&["Banana Acme", "B4n4n4 4cme"]));
+    }
+}
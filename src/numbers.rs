@@ -0,0 +1,337 @@
+use regex::{Captures, Regex};
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::configuration;
+use super::parser::WordKind;
+
+lazy_static! {
+    static ref SETTINGS: NumberSettings = read_settings();
+    // "1969-07-20"
+    static ref ISO_DATE_REGEX: Regex = Regex::new(r"\b(\d{4})-(\d{1,2})-(\d{1,2})\b").unwrap();
+    // "07/20/1969", "20.07.69"
+    static ref NUMERIC_DATE_REGEX: Regex = Regex::new(r"\b(\d{1,2})[/.](\d{1,2})[/.](\d{4}|\d{2})\b").unwrap();
+    // "July 20, 1969", "Jul. 20th 1969"
+    static ref MONTH_FIRST_DATE_REGEX: Regex = Regex::new(
+        &format!(r"(?i)\b{}\.?\s+(\d{{1,2}})(?:st|nd|rd|th)?,?\s+(\d{{4}})\b", MONTH_PATTERN)).unwrap();
+    // "20 July 1969", "20th of July, 1969"
+    static ref DAY_FIRST_DATE_REGEX: Regex = Regex::new(
+        &format!(r"(?i)\b(\d{{1,2}})(?:st|nd|rd|th)?\s+(?:of\s+)?{}\.?,?\s+(\d{{4}})\b", MONTH_PATTERN)).unwrap();
+}
+
+const MONTH_PATTERN: &str = r"(jan(?:uary)?|feb(?:ruary)?|mar(?:ch)?|apr(?:il)?|may|june?|july?|aug(?:ust)?|sept?(?:ember)?|oct(?:ober)?|nov(?:ember)?|dec(?:ember)?)";
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+// endings that make a number an ordinal ("21st") rather than a model number ("A320")
+const ORDINAL_SUFFIXES: [&str; 4] = ["st", "nd", "rd", "th"];
+
+// turns a date regex's captures into the date without separators and its four digit year (if it has one)
+type DateConverter = fn(&Captures) -> Option<(String, String)>;
+
+#[derive(Clone, Debug)]
+pub struct NumberSettings {
+    enabled: bool,
+    min_digits: usize,
+    max_digits: usize,
+    dates: bool,
+    model_numbers: bool,
+    pairings: bool,
+}
+
+// the numbers, years, dates, model numbers and pairings in a block of text, along with what kind of token each one is
+// dates are written without their separators ("1969-07-20" is "19690720") since that's how they end up in passwords
+pub fn extract(text: &str) -> Vec<(String, WordKind)> {
+    extract_with(text, &SETTINGS)
+}
+
+fn extract_with(text: &str, settings: &NumberSettings) -> Vec<(String, WordKind)> {
+    let mut result = Vec::new();
+    if !settings.enabled {
+        return result;
+    }
+
+    // dates come out first and are blanked so their pieces aren't also counted as numbers
+    let mut remaining = text.to_string();
+    if settings.dates {
+        let date_regexes: [(&Regex, DateConverter); 4] = [
+            (&ISO_DATE_REGEX, iso_date),
+            (&NUMERIC_DATE_REGEX, numeric_date),
+            (&MONTH_FIRST_DATE_REGEX, month_first_date),
+            (&DAY_FIRST_DATE_REGEX, day_first_date),
+        ];
+        for (regex, convert) in date_regexes.iter() {
+            for captures in regex.captures_iter(&remaining.clone()) {
+                if let Some((date, year)) = convert(&captures) {
+                    result.push((date, WordKind::Date));
+                    if !year.is_empty() {
+                        result.push((year, WordKind::Date));
+                    }
+                    let whole = captures.get(0).unwrap();
+                    remaining.replace_range(whole.range(), &" ".repeat(whole.as_str().len()));
+                }
+            }
+        }
+    }
+
+    for word in remaining.unicode_words() {
+        let digits: String = word.chars().filter(char::is_ascii_digit).collect();
+        if digits.is_empty() {
+            continue;
+        }
+        let letters = word.chars().filter(|character| character.is_alphabetic()).count();
+        if letters == 0 {
+            // "1,000,000" and "3.14" are single words, so the separators are dropped
+            if digits.len() < settings.min_digits || digits.len() > settings.max_digits {
+                continue;
+            }
+            if settings.dates && is_year(&digits) {
+                result.push((digits, WordKind::Date));
+            } else {
+                result.push((digits, WordKind::Number));
+            }
+        } else if settings.model_numbers && !is_ordinal(word) {
+            // letters and digits together are model numbers, call signs and the like ("A320", "B52", "R2D2")
+            result.push((word.to_string(), WordKind::Number));
+        }
+    }
+
+    if settings.pairings {
+        for paired in pairings(&remaining, settings) {
+            result.push((paired, WordKind::Pairing));
+        }
+    }
+
+    result
+}
+
+// words with a number right next to them joined together ("Apollo 11" is "Apollo11", "1969 Mustang" is "Mustang1969")
+fn pairings(text: &str, settings: &NumberSettings) -> Vec<String> {
+    let mut result = Vec::new();
+
+    // the last word or number seen, as long as only spaces and hyphens have come since
+    let mut previous: Option<&str> = None;
+    for segment in text.split_word_bounds() {
+        if segment.chars().all(char::is_whitespace) || segment == "-" || segment == "#" {
+            continue;
+        }
+        let is_number = segment.chars().all(|character| character.is_ascii_digit());
+        let is_word = segment.chars().all(char::is_alphabetic) && is_pairable_word(segment);
+        if let Some(previous_segment) = previous {
+            let previous_is_number = previous_segment.chars().all(|character| character.is_ascii_digit());
+            let paired = if is_number && !previous_is_number && fits(segment, settings) {
+                Some(format!("{}{}", previous_segment, segment))
+            } else if is_word && previous_is_number && fits(previous_segment, settings) {
+                Some(format!("{}{}", segment, previous_segment))
+            } else {
+                None
+            };
+            if let Some(paired) = paired {
+                result.push(paired);
+                // "Apollo 11 mission" is "Apollo11", not also "mission11"
+                previous = None;
+                continue;
+            }
+        }
+        previous = if is_number || is_word { Some(segment) } else { None };
+    }
+
+    result
+}
+
+fn fits(number: &str, settings: &NumberSettings) -> bool {
+    // single digits are fine next to a word ("Apollo 9"), but not absurdly long numbers
+    number.len() <= settings.max_digits
+}
+
+// lowercase words next to a number are usually just a sentence ("flew 13 times"), names are what we're after
+fn is_pairable_word(word: &str) -> bool {
+    word.chars().next().map(char::is_uppercase).unwrap_or(false)
+}
+
+fn is_year(digits: &str) -> bool {
+    match digits.parse::<u32>() {
+        Ok(year) => digits.len() == 4 && (1900..=2099).contains(&year),
+        Err(_) => false
+    }
+}
+
+fn is_ordinal(word: &str) -> bool {
+    let lower = word.to_lowercase();
+    ORDINAL_SUFFIXES.iter().any(|suffix| {
+        lower.ends_with(suffix) && lower[..lower.len() - suffix.len()].chars().all(|character| character.is_ascii_digit())
+    })
+}
+
+fn is_month(number: u32) -> bool {
+    (1..=12).contains(&number)
+}
+
+fn is_day(number: u32) -> bool {
+    (1..=31).contains(&number)
+}
+
+fn month_number(name: &str) -> usize {
+    let prefix: String = name.to_lowercase().chars().take(3).collect();
+    MONTHS.iter().position(|month| *month == prefix).unwrap_or(0) + 1
+}
+
+// every part is padded to two digits, in the order it was written
+fn iso_date(captures: &Captures) -> Option<(String, String)> {
+    let (year, month, day) = (&captures[1], &captures[2], &captures[3]);
+    if !is_month(month.parse().ok()?) || !is_day(day.parse().ok()?) {
+        return None;
+    }
+    Some((format!("{}{:0>2}{:0>2}", year, month, day), year.to_string()))
+}
+
+fn numeric_date(captures: &Captures) -> Option<(String, String)> {
+    let (first, second, year) = (&captures[1], &captures[2], &captures[3]);
+    // "1.5.10" is more likely a version number than a date
+    if year.len() == 2 && (first.len() < 2 || second.len() < 2) {
+        return None;
+    }
+    // either order is fine ("07/20" or "20.07"), but one of them has to be the month
+    let (first_number, second_number) = (first.parse::<u32>().ok()?, second.parse::<u32>().ok()?);
+    if !(is_month(first_number) || is_month(second_number)) || !is_day(first_number) || !is_day(second_number) {
+        return None;
+    }
+    let full_year = if year.len() == 4 { year.to_string() } else { String::new() };
+    Some((format!("{:0>2}{:0>2}{}", first, second, year), full_year))
+}
+
+fn month_first_date(captures: &Captures) -> Option<(String, String)> {
+    let (month, day, year) = (month_number(&captures[1]), &captures[2], &captures[3]);
+    if !is_day(day.parse().ok()?) {
+        return None;
+    }
+    Some((format!("{:02}{:0>2}{}", month, day, year), year.to_string()))
+}
+
+fn day_first_date(captures: &Captures) -> Option<(String, String)> {
+    let (day, month, year) = (&captures[1], month_number(&captures[2]), &captures[3]);
+    if !is_day(day.parse().ok()?) {
+        return None;
+    }
+    Some((format!("{:0>2}{:02}{}", day, month, year), year.to_string()))
+}
+
+fn read_settings() -> NumberSettings {
+    let config = configuration::CONFIGURATION.read().unwrap();
+
+    NumberSettings {
+        enabled: config.get_bool("numbers.enabled").unwrap_or(true),
+        min_digits: match config.get_int("numbers.min_digits") {
+            Ok(value) if value >= 1 => value as usize,
+            _ => 2
+        },
+        max_digits: match config.get_int("numbers.max_digits") {
+            Ok(value) if value >= 1 => value as usize,
+            _ => 8
+        },
+        dates: config.get_bool("numbers.dates").unwrap_or(true),
+        model_numbers: config.get_bool("numbers.model_numbers").unwrap_or(true),
+        pairings: config.get_bool("numbers.pairings").unwrap_or(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> NumberSettings {
+        NumberSettings {
+            enabled: true,
+            min_digits: 2,
+            max_digits: 8,
+            dates: true,
+            model_numbers: true,
+            pairings: false,
+        }
+    }
+
+    fn tokens(text: &str, settings: &NumberSettings) -> Vec<(String, WordKind)> {
+        extract_with(text, settings)
+    }
+
+    fn token(text: &str, kind: WordKind) -> (String, WordKind) {
+        (text.to_string(), kind)
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(tokens("landed 1969-07-20", &settings()), vec![token("19690720", WordKind::Date), token("1969", WordKind::Date)]);
+        assert_eq!(tokens("on 7/20/1969", &settings()), vec![token("07201969", WordKind::Date), token("1969", WordKind::Date)]);
+        assert_eq!(tokens("on 20.07.69", &settings()), vec![token("200769", WordKind::Date)]);
+        assert_eq!(tokens("July 20, 1969", &settings()), vec![token("07201969", WordKind::Date), token("1969", WordKind::Date)]);
+        assert_eq!(tokens("Sept. 3rd 2001", &settings()), vec![token("09032001", WordKind::Date), token("2001", WordKind::Date)]);
+        assert_eq!(tokens("the 20th of July, 1969", &settings()), vec![token("20071969", WordKind::Date), token("1969", WordKind::Date)]);
+    }
+
+    #[test]
+    fn impossible_dates_arent_dates() {
+        // neither part can be the month
+        assert_eq!(tokens("on 20/20/1999", &settings()),
+                   vec![token("20", WordKind::Number), token("20", WordKind::Number), token("1999", WordKind::Date)]);
+        // no month has a 45th day
+        assert_eq!(tokens("on 45/07/1999", &settings()),
+                   vec![token("45", WordKind::Number), token("07", WordKind::Number), token("1999", WordKind::Date)]);
+        assert_eq!(tokens("on 00/00/2000", &settings()),
+                   vec![token("00", WordKind::Number), token("00", WordKind::Number), token("2000", WordKind::Date)]);
+        assert_eq!(tokens("July 45, 1969", &settings()), vec![token("45", WordKind::Number), token("1969", WordKind::Date)]);
+        assert_eq!(tokens("45 July 1969", &settings()), vec![token("45", WordKind::Number), token("1969", WordKind::Date)]);
+        assert_eq!(tokens("1969-13-20", &settings()),
+                   vec![token("1969", WordKind::Date), token("13", WordKind::Number), token("20", WordKind::Number)]);
+        // a day first date is fine
+        assert_eq!(tokens("on 31/12/1999", &settings()), vec![token("31121999", WordKind::Date), token("1999", WordKind::Date)]);
+    }
+
+    #[test]
+    fn version_numbers_arent_dates() {
+        assert_eq!(tokens("version 1.5.10", &settings()), vec![token("1510", WordKind::Number)]);
+    }
+
+    #[test]
+    fn numbers_and_years() {
+        assert_eq!(tokens("founded in 1949 with 1,000,000 dollars", &settings()),
+                   vec![token("1949", WordKind::Date), token("1000000", WordKind::Number)]);
+        // outside of the digit limits, and years outside 1900-2099
+        assert_eq!(tokens("7 and 123456789 and 1850", &settings()), vec![token("1850", WordKind::Number)]);
+    }
+
+    #[test]
+    fn model_numbers_but_not_ordinals() {
+        assert_eq!(tokens("the A320 and R2D2 came 21st", &settings()),
+                   vec![token("A320", WordKind::Number), token("R2D2", WordKind::Number)]);
+        let settings = NumberSettings { model_numbers: false, ..settings() };
+        assert!(tokens("the A320", &settings).is_empty());
+    }
+
+    #[test]
+    fn disabled_settings() {
+        let no_dates = NumberSettings { dates: false, ..settings() };
+        assert_eq!(tokens("1969-07-20", &no_dates),
+                   vec![token("1969", WordKind::Number), token("07", WordKind::Number), token("20", WordKind::Number)]);
+        let disabled = NumberSettings { enabled: false, ..settings() };
+        assert!(tokens("1969-07-20 and A320", &disabled).is_empty());
+    }
+
+    #[test]
+    fn words_are_paired_with_the_number_next_to_them() {
+        let settings = NumberSettings { pairings: true, ..settings() };
+        assert_eq!(pairings("Apollo 11 mission", &settings), vec!["Apollo11"]);
+        assert_eq!(pairings("a 1969 Mustang", &settings), vec!["Mustang1969"]);
+        assert_eq!(pairings("Route-66 and Agent #7", &settings), vec!["Route66", "Agent7"]);
+        // lowercase words are usually just a sentence, and long numbers aren't worth it
+        assert!(pairings("flew 13 times", &settings).is_empty());
+        assert!(pairings("Order 1234567890", &settings).is_empty());
+        assert!(tokens("Apollo 11", &settings).contains(&token("Apollo11", WordKind::Pairing)));
+    }
+
+    #[test]
+    fn helpers() {
+        assert!(is_year("1999") && !is_year("1899") && !is_year("199"));
+        assert!(is_ordinal("21st") && is_ordinal("3RD") && !is_ordinal("A320") && !is_ordinal("1st2"));
+        assert_eq!(month_number("September"), 9);
+        assert_eq!(month_number("dec"), 12);
+    }
+}
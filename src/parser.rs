@@ -1,112 +1,430 @@
+use std::collections::HashMap;
 use regex::Regex;
+use scraper::{ElementRef, Html, Node, Selector};
+use scraper::node::Element;
+use unicode_segmentation::UnicodeSegmentation;
+use url::Url;
 
-pub fn parse(response_text: &mut String) -> (Vec<String>, Vec<String>) {
+use super::numbers;
+use super::phrases;
 
-    //removes junk text
-    remove_scripts(response_text); // remove JavaScript elements
-    remove_style(response_text); // remove CSS
-    remove_html_nodes(response_text); // remove HTML nodes
-    remove_html_text(response_text); // remove HTML encoded text (e.g. "&nbsp;")
-    remove_numbers(response_text); // remove any numbers
+// elements that never contain text a visitor would read
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "script", "style", "noscript", "template", "svg", "math", "object", "iframe", "canvas",
+];
 
-    let critical = gather_critical_words(response_text);
-    let extracted = extract_words(response_text);
+// elements whose text stands on its own, so it isn't glued to the text around it
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "body", "br", "button", "caption", "dd", "details",
+    "dialog", "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2",
+    "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "label", "legend", "li", "main",
+    "nav", "ol", "option", "p", "pre", "section", "select", "summary", "table", "tbody", "td",
+    "textarea", "tfoot", "th", "thead", "title", "tr", "ul",
+];
+
+lazy_static! {
+    static ref LINK_SELECTOR: Selector = Selector::parse("a[href]").unwrap();
+    static ref META_SELECTOR: Selector = Selector::parse("meta[content]").unwrap();
+    static ref ALT_SELECTOR: Selector = Selector::parse("img[alt], area[alt], input[alt]").unwrap();
+    static ref ABBREVIATION_SELECTOR: Selector = Selector::parse("abbr[title], acronym[title]").unwrap();
+    // compiled once since it's run over every block of every page
+    static ref NUMBER_REGEX: Regex = Regex::new(r"\d+(?:\.\d+)?").unwrap();
+}
+
+// the <meta> tags that describe what the page is about
+const META_NAMES: &[&str] = &[
+    "keywords", "description", "author", "application-name", "og:title", "og:description",
+    "og:site_name", "twitter:title", "twitter:description",
+];
+
+// where on the page a word was found, which the finalizer uses to weigh it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WordSource {
+    Body,
+    Title,
+    Meta,
+    Heading,
+    ImageAlt,
+    Abbreviation,
+    Snippet,  // an engine's title or snippet for the page in its results
+}
+
+impl WordSource {
+    pub const ALL: [WordSource; 7] = [
+        WordSource::Body, WordSource::Title, WordSource::Meta,
+        WordSource::Heading, WordSource::ImageAlt, WordSource::Abbreviation,
+        WordSource::Snippet,
+    ];
+
+    // the key used for this source in the "weights" table of the configuration
+    pub fn name(self) -> &'static str {
+        match self {
+            WordSource::Body => "body",
+            WordSource::Title => "title",
+            WordSource::Meta => "meta",
+            WordSource::Heading => "heading",
+            WordSource::ImageAlt => "alt",
+            WordSource::Abbreviation => "abbr",
+            WordSource::Snippet => "snippet",
+        }
+    }
+}
+
+// what sort of token a word is, which the finalizer also uses to weigh it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WordKind {
+    Text,
+    Number,  // numbers and model numbers ("A320")
+    Date,  // years and dates without their separators ("19690720")
+    Pairing,  // a word and a number found next to each other ("Apollo11")
+}
+
+impl WordKind {
+    // the key used for this kind in the "weights" table of the configuration
+    pub fn name(self) -> &'static str {
+        match self {
+            WordKind::Text => "text",
+            WordKind::Number => "number",
+            WordKind::Date => "date",
+            WordKind::Pairing => "pairing",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Word {
+    pub text: String,
+    pub source: WordSource,
+    pub kind: WordKind,
+    pub critical: bool,
+}
+
+pub fn parse(document: &Html) -> (Vec<Word>, Vec<Word>) {
+    let mut critical = Vec::new();
+    let mut extracted = Vec::new();
+
+    // each block of text is handled on its own so words from different elements never run together
+    let blocks = extract_text_blocks(document).into_iter()
+        .chain(extract_attribute_text(document));
+    for (source, block) in blocks {
+        parse_block(source, block, &mut critical, &mut extracted);
+    }
 
     //return tuple
     (critical, extracted)
 }
 
-// 'critical words' are things like pronouns or important nouns that are likely to be passwords
-fn gather_critical_words(response_text: &String) -> Vec<String> {
-    let cap_rex = Regex::new(r"(?P<cap>(?:[A-Z][\w]*)(?:[ \-]?[A-Z][\w]*)*)").unwrap();
+// the same as parse, for text that didn't come from a page (e.g. an engine's result snippets)
+pub fn parse_text(source: WordSource, text: &str) -> (Vec<Word>, Vec<Word>) {
+    let mut critical = Vec::new();
+    let mut extracted = Vec::new();
+    parse_block(source, text.to_string(), &mut critical, &mut extracted);
 
-    let mut result: Vec<String> = Vec::new();
+    (critical, extracted)
+}
 
-    for capture in cap_rex.captures_iter(response_text) {
-        //for every capture (many contain multiple words), pull out the words
-        let word_rex = Regex::new(r"[a-zA-Z]+").unwrap();
-        //collect them into a vector
-        let word_vector: Vec<String> = word_rex.captures_iter(&capture[0])
-            .map(|cap| cap.get(0).unwrap().as_str().to_string())
-            .collect();
-
-        //and find the powerset of each (all variations)
-        for word in word_vector {
-            let split_word = split_string_to_vector(&word);
-            let word_variations = get_powerset_of_vector(&split_word);
-
-            for variant in word_variations {
-                result.push(variant);
-            }
+fn parse_block(source: WordSource, mut block: String, critical: &mut Vec<Word>, extracted: &mut Vec<Word>) {
+    // numbers and dates are their own kind of token, so they're taken out before the words are
+    for (text, kind) in numbers::extract(&block) {
+        extracted.push(Word { text, source, kind, critical: false });
+    }
+
+    remove_numbers(&mut block); // remove any numbers
+
+    // a capitalized word is both a critical word and a plain one, but it was only found once so it's only counted once
+    let mut critical_counts: HashMap<String, usize> = HashMap::new();
+    for text in gather_critical_words(&block) {
+        *critical_counts.entry(text.clone()).or_insert(0) += 1;
+        critical.push(Word { text, source, kind: WordKind::Text, critical: true });
+    }
+    for text in extract_words(&block) {
+        match critical_counts.get_mut(&text) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => extracted.push(Word { text, source, kind: WordKind::Text, critical: false })
         }
     }
+}
 
-    result
+// walks the DOM and returns the visible text, one string per block-level element
+pub fn extract_text_blocks(document: &Html) -> Vec<(WordSource, String)> {
+    let mut blocks = Vec::new();
+    let mut current = String::new();
+
+    collect_text(document.root_element(), WordSource::Body, &mut blocks, &mut current);
+    finish_block(&mut blocks, &mut current, WordSource::Body);
+
+    blocks
 }
 
-fn split_string_to_vector(string: &String) -> Vec<String> {
-    string.split_whitespace().map(|val| val.to_string()).collect()
+// text that only lives in attributes: <meta> descriptions, image alt text and abbreviation titles
+fn extract_attribute_text(document: &Html) -> Vec<(WordSource, String)> {
+    let mut blocks = Vec::new();
+
+    for meta in document.select(&META_SELECTOR) {
+        let name = meta.value().attr("name")
+            .or_else(|| meta.value().attr("property"))
+            .unwrap_or("")
+            .to_lowercase();
+        if !META_NAMES.contains(&name.as_str()) {
+            continue;
+        }
+        let content = meta.value().attr("content").unwrap_or("");
+        if name == "keywords" {
+            // every keyword is its own phrase
+            for keyword in content.split(',') {
+                blocks.push((WordSource::Meta, keyword.trim().to_string()));
+            }
+        } else {
+            blocks.push((WordSource::Meta, content.to_string()));
+        }
+    }
+    for image in document.select(&ALT_SELECTOR) {
+        blocks.push((WordSource::ImageAlt, image.value().attr("alt").unwrap_or("").to_string()));
+    }
+    for abbreviation in document.select(&ABBREVIATION_SELECTOR) {
+        blocks.push((WordSource::Abbreviation, abbreviation.value().attr("title").unwrap_or("").to_string()));
+    }
+
+    blocks.retain(|(_, text)| !text.trim().is_empty());
+    blocks
 }
 
-fn get_powerset_of_vector(vector: &Vec<String>) -> Vec<String> {
-    let length = vector.len();
-    let mut result = Vec::new();
+fn collect_text(element: ElementRef,
+                source: WordSource,
+                blocks: &mut Vec<(WordSource, String)>,
+                current: &mut String) {
+    if is_skipped(element.value()) {
+        return;
+    }
+
+    let name = element.value().name();
+    let element_source = match name {
+        "title" => WordSource::Title,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => WordSource::Heading,
+        _ => source
+    };
+
+    let is_block = BLOCK_ELEMENTS.contains(&name);
+    if is_block {
+        finish_block(blocks, current, source);
+    }
 
-    for setnum in 0..1<<length { // bit shift for ^2 of the length to get correct size of powerset
-        // create the list for the individual powerset
-        let mut set = Vec::new();
-        // build each possible element
-        for index in 0..setnum {
-            if setnum & (1<<index) > 0 {
-                set.push(vector[index].clone());
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) => current.push_str(text),
+            Node::Element(_) => {
+                if let Some(child_element) = ElementRef::wrap(child) {
+                    collect_text(child_element, element_source, blocks, current);
+                }
             }
+            _ => ()  // comments, CDATA, doctypes, etc. aren't visible text
+        }
+    }
+
+    if is_block {
+        finish_block(blocks, current, element_source);
+    }
+}
+
+// ends the block of text currently being built, squashing all of its whitespace down to single spaces
+fn finish_block(blocks: &mut Vec<(WordSource, String)>, current: &mut String, source: WordSource) {
+    let block = current.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if !block.is_empty() {
+        blocks.push((source, block));
+    }
+    current.clear();
+}
+
+fn is_skipped(element: &Element) -> bool {
+    if SKIPPED_ELEMENTS.contains(&element.name()) {
+        return true;
+    }
+
+    // anything the page hides from visitors is also hidden from us
+    if element.attr("hidden").is_some() {
+        return true;
+    }
+    if let Some(aria_hidden) = element.attr("aria-hidden") {
+        if aria_hidden.trim().eq_ignore_ascii_case("true") {
+            return true;
         }
-        // flatten the powerset and join it into the vector of all powersets
-        let mut flattened_set = String::new();
-        for value in set {
-            flattened_set = format!("{} {}", flattened_set, value);
+    }
+    if let Some(style) = element.attr("style") {
+        let style: String = style.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase();
+        if style.contains("display:none") || style.contains("visibility:hidden") {
+            return true;
         }
-        result.push(flattened_set.trim().to_string());
     }
 
-    // return the list of powerset elements
-    result
+    false
 }
 
-fn extract_words(text: &String) -> Vec<String> {
-    let rex = Regex::new(r"\w+").unwrap();
-    let result: Vec<String> = rex.captures_iter(text)
-        .filter(|cap| cap.get(0).unwrap().as_str().len() > 4)
-        .map(|cap| cap.get(0).unwrap().as_str().to_string())
-        .collect();
+// pulls the target of every <a href> on the page, resolved against the page's own URL
+pub fn extract_links(document: &Html, page_url: &str) -> Vec<String> {
+    let base_url = match Url::parse(page_url) {
+        Ok(url) => url,
+        Err(_) => return Vec::new()
+    };
+
+    let mut result: Vec<String> = Vec::new();
+    for anchor in document.select(&LINK_SELECTOR) {
+        let href = anchor.value().attr("href").unwrap_or("").trim();
+        match base_url.join(href) {
+            Ok(mut full_url) => {
+                // skip mailto:, javascript: and the like
+                if full_url.scheme() != "http" && full_url.scheme() != "https" {
+                    continue;
+                }
+                // anchors on the same page would otherwise be fetched again
+                full_url.set_fragment(None);
+                let link = full_url.to_string();
+                if !result.contains(&link) {
+                    result.push(link);
+                }
+            }
+            Err(_) => continue
+        }
+    }
+
     result
 }
 
-fn remove_scripts(text: &mut String) {
-    let rex = Regex::new(r"<script[\s\S]*?>[\s\S]*?</script>").unwrap();
-    *text = rex.replace_all(text, "").to_string();
+// 'critical words' are things like pronouns or important nouns that are likely to be passwords
+fn gather_critical_words(response_text: &str) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+
+    //for every run of capitalized words (many contain multiple words), build every phrase from it
+    for word_vector in capitalized_runs(response_text) {
+        result.append(&mut phrases::generate(&word_vector));
+    }
+
+    result
 }
 
-fn remove_style(text: &mut String) {
-    let rex = Regex::new(r"<style[\s\S]*?>[\s\S]*?</style>").unwrap();
-    *text = rex.replace_all(text, "").to_string();
+// splits the text into runs of consecutive capitalized words (e.g. "Golden Gate Bridge" or "Władysław Reymont")
+fn capitalized_runs(text: &str) -> Vec<Vec<String>> {
+    let mut runs = Vec::new();
+    let mut current: Vec<String> = Vec::new();
+
+    for segment in text.split_word_bounds() {
+        if is_word(segment) {
+            if starts_uppercase(segment) {
+                current.push(segment.to_string());
+                continue;
+            }
+        } else if segment.chars().all(char::is_whitespace) || segment == "-" {
+            // spaces and hyphens don't break up a name
+            continue;
+        }
+
+        // anything else (a lowercase word, punctuation) ends the run
+        if !current.is_empty() {
+            runs.push(current);
+            current = Vec::new();
+        }
+    }
+    if !current.is_empty() {
+        runs.push(current);
+    }
+
+    runs
 }
 
-fn remove_html_nodes(text: &mut String) {
-    let rex = Regex::new(r"<[\s\S]*?>").unwrap();
-    *text = rex.replace_all(text, "").to_string();
+// a word segment is any segment with a letter or digit in it, the rest are spaces and punctuation
+fn is_word(segment: &str) -> bool {
+    segment.chars().any(char::is_alphanumeric)
 }
 
-fn remove_html_text(text: &mut String) {
-    // replace non-breaking spaces with actual spaces
-    *text = text.replace("&nbsp;", " ");
+// uses Unicode's idea of uppercase, so "Éric" and "Жуков" count as capitalized
+fn starts_uppercase(word: &str) -> bool {
+    match word.chars().next() {
+        Some(first) => first.is_uppercase(),
+        None => false
+    }
+}
 
-    // just remove all other html characters
-    let rex = Regex::new(r"&.*?;").unwrap();
-    *text = rex.replace_all(text, "").to_string();
+fn extract_words(text: &str) -> Vec<String> {
+    let result: Vec<String> = text.unicode_words()
+        .filter(|word| word.chars().count() > 4)
+        .map(|word| word.to_string())
+        .collect();
+    result
 }
 
 fn remove_numbers(text: &mut String) {
-    let rex = Regex::new(r"\d+(?:\.\d+)?").unwrap();
-    *text = rex.replace_all(text, "").to_string();
-}
\ No newline at end of file
+    *text = NUMBER_REGEX.replace_all(text, "").to_string();
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(html: &str) -> Vec<(WordSource, String)> {
+        extract_text_blocks(&Html::parse_document(html))
+    }
+
+    fn body(texts: &[&str]) -> Vec<(WordSource, String)> {
+        texts.iter().map(|text| (WordSource::Body, text.to_string())).collect()
+    }
+
+    #[test]
+    fn blocks_end_at_block_elements() {
+        let html = "<p>Acme <b>Rocket</b>   Skates</p><div>Wile<span>E</span>Coyote<br>Road\nRunner</div>\
+                    <ul><li>Anvils</li><li>Magnets</li></ul>loose <em>text</em>";
+        assert_eq!(blocks(html), body(&["Acme Rocket Skates", "WileECoyote", "Road Runner", "Anvils", "Magnets", "loose text"]));
+    }
+
+    #[test]
+    fn hidden_elements_are_skipped() {
+        let html = r#"<p>shown</p>
+            <p hidden>hidden attribute</p>
+            <p aria-hidden="true">aria hidden</p>
+            <p aria-hidden="false">aria shown</p>
+            <div style="display: none">display none</div>
+            <div style="color: red; VISIBILITY:hidden">visibility hidden</div>
+            <div style="visibility: visible">visible</div>"#;
+        assert_eq!(blocks(html), body(&["shown", "aria shown", "visible"]));
+    }
+
+    #[test]
+    fn scripts_and_styles_are_dropped() {
+        let html = "<head><style>body { color: red }</style><script>var secret = 'Password';</script></head>\
+                    <body><p>Acme<script>document.write('Injected')</script> Rockets</p><noscript>Enable JavaScript</noscript>\
+                    <!-- a comment --></body>";
+        assert_eq!(blocks(html), body(&["Acme Rockets"]));
+    }
+
+    #[test]
+    fn words_keep_where_they_were_found() {
+        let html = r#"<html><head>
+            <title>Rocket Catalog</title>
+            <meta name="description" content="discount anvils">
+            <meta name="keywords" content="magnets, explosives">
+            <meta name="robots" content="noindex nofollow">
+            </head><body>
+            <h2>featured skates</h2>
+            <p>genuine dynamite <abbr title="widget corporation">wco</abbr></p>
+            <img src="tunnel.png" alt="painted tunnel">
+            </body></html>"#;
+        let (critical, extracted) = parse(&Html::parse_document(html));
+
+        let mut found: Vec<(String, WordSource)> = extracted.iter().map(|word| (word.text.clone(), word.source)).collect();
+        found.sort_by(|first, second| first.0.cmp(&second.0));
+        let mut expected: Vec<(String, WordSource)> = vec![
+            ("discount", WordSource::Meta), ("anvils", WordSource::Meta),
+            ("magnets", WordSource::Meta), ("explosives", WordSource::Meta),
+            ("featured", WordSource::Heading), ("skates", WordSource::Heading),
+            ("genuine", WordSource::Body), ("dynamite", WordSource::Body),
+            ("painted", WordSource::ImageAlt), ("tunnel", WordSource::ImageAlt),
+            ("widget", WordSource::Abbreviation), ("corporation", WordSource::Abbreviation),
+        ].into_iter().map(|(text, source)| (text.to_string(), source)).collect();
+        expected.sort_by(|first, second| first.0.cmp(&second.0));
+        assert_eq!(found, expected);
+        assert!(extracted.iter().all(|word| word.kind == WordKind::Text && !word.critical));
+
+        // the capitalized title only shows up as critical words, not again as plain ones
+        assert!(critical.iter().all(|word| word.source == WordSource::Title && word.critical));
+        for text in &["Rocket", "Catalog", "RocketCatalog"] {
+            assert!(critical.iter().any(|word| word.text == *text), "{} is missing", text);
+        }
+    }
+}
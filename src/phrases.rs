@@ -0,0 +1,208 @@
+use super::configuration;
+
+// how the words of a phrase are glued together
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JoinStyle {
+    Plain,  // "GoldenGate"
+    Space,  // "Golden Gate"
+    Underscore,  // "Golden_Gate"
+    Hyphen,  // "Golden-Gate"
+    Camel,  // "GoldenGate", only the first letters change so "McDonald" stays "McDonald"
+    LowerCamel,  // "goldenGate"
+}
+
+impl JoinStyle {
+    fn from_name(name: &str) -> Option<JoinStyle> {
+        match name.to_lowercase().as_str() {
+            "none" => Some(JoinStyle::Plain),
+            "space" => Some(JoinStyle::Space),
+            "underscore" | "_" => Some(JoinStyle::Underscore),
+            "hyphen" | "-" => Some(JoinStyle::Hyphen),
+            "camel" | "camelcase" => Some(JoinStyle::Camel),
+            "lowercamel" | "lowercamelcase" => Some(JoinStyle::LowerCamel),
+            _ => None
+        }
+    }
+
+    fn join(self, words: &[&String]) -> String {
+        match self {
+            JoinStyle::Plain => words.iter().map(|word| word.as_str()).collect::<Vec<&str>>().join(""),
+            JoinStyle::Space => words.iter().map(|word| word.as_str()).collect::<Vec<&str>>().join(" "),
+            JoinStyle::Underscore => words.iter().map(|word| word.as_str()).collect::<Vec<&str>>().join("_"),
+            JoinStyle::Hyphen => words.iter().map(|word| word.as_str()).collect::<Vec<&str>>().join("-"),
+            JoinStyle::Camel | JoinStyle::LowerCamel => {
+                let mut result = String::new();
+                for (index, word) in words.iter().enumerate() {
+                    let mut characters = word.chars();
+                    if let Some(first) = characters.next() {
+                        if index == 0 && self == JoinStyle::LowerCamel {
+                            result.extend(first.to_lowercase());
+                        } else {
+                            result.extend(first.to_uppercase());
+                        }
+                        result.push_str(characters.as_str());
+                    }
+                }
+                result
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PhraseSettings {
+    max_words: usize,
+    subsets: bool,
+    max_subset_run: usize,
+    join_styles: Vec<JoinStyle>,
+}
+
+lazy_static! {
+    static ref SETTINGS: PhraseSettings = read_settings();
+}
+
+// turns a run of consecutive capitalized words into every phrase we want to try as a password
+pub fn generate(run: &[String]) -> Vec<String> {
+    let settings: &PhraseSettings = &SETTINGS;
+    let mut result = Vec::new();
+    let longest = settings.max_words.min(run.len());
+
+    // every word on its own, then every contiguous n-gram ("Golden Gate", "Gate Bridge", ...)
+    for length in 1..=longest {
+        for window in run.windows(length) {
+            push_joined(&mut result, &window.iter().collect::<Vec<&String>>(), settings);
+        }
+    }
+
+    // then the ordered subsets that skip words ("Golden Bridge"), unless there'd be too many of them
+    if settings.subsets && run.len() <= settings.max_subset_run {
+        for length in 2..=longest {
+            for indexes in combinations(run.len(), length) {
+                let contiguous = indexes.windows(2).all(|pair| pair[1] == pair[0] + 1);
+                if !contiguous {
+                    let words: Vec<&String> = indexes.iter().map(|&index| &run[index]).collect();
+                    push_joined(&mut result, &words, settings);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+fn push_joined(result: &mut Vec<String>, words: &[&String], settings: &PhraseSettings) {
+    if words.len() == 1 {
+        result.push(words[0].clone());
+        return;
+    }
+    for style in &settings.join_styles {
+        result.push(style.join(words));
+    }
+}
+
+// every sorted selection of `length` indexes out of 0..count
+fn combinations(count: usize, length: usize) -> Vec<Vec<usize>> {
+    let mut result = Vec::new();
+    let mut indexes: Vec<usize> = (0..length).collect();
+    if length == 0 || length > count {
+        return result;
+    }
+    loop {
+        result.push(indexes.clone());
+
+        // find the rightmost index that can still move right
+        let mut position = length;
+        while position > 0 && indexes[position - 1] == count - length + position - 1 {
+            position -= 1;
+        }
+        if position == 0 {
+            return result;
+        }
+        indexes[position - 1] += 1;
+        for next in position..length {
+            indexes[next] = indexes[next - 1] + 1;
+        }
+    }
+}
+
+fn read_settings() -> PhraseSettings {
+    let config = configuration::CONFIGURATION.read().unwrap();
+
+    let max_words = match config.get_int("phrases.max_words") {
+        Ok(value) if value >= 1 => value as usize,
+        Ok(_) => {
+            println!("!!!  \"phrases.max_words\" must be at least 1. Defaulting to 1");
+            1
+        }
+        Err(_) => 3
+    };
+    let max_subset_run = match config.get_int("phrases.max_subset_run") {
+        Ok(value) if value >= 0 => value as usize,
+        _ => 8
+    };
+    let join_styles = match config.get_array("phrases.join_styles") {
+        Ok(values) => values.into_iter()
+            .filter_map(|value| match value.into_str() {
+                Ok(name) => {
+                    let style = JoinStyle::from_name(&name);
+                    if style.is_none() {
+                        println!("!!!  Unknown phrase join style \"{}\" will be ignored", name);
+                    }
+                    style
+                }
+                Err(e) => {
+                    println!("!!!  Phrase join style isn't a valid string: {}", e);
+                    None
+                }
+            })
+            .collect(),
+        Err(_) => vec![JoinStyle::Plain]
+    };
+
+    PhraseSettings {
+        max_words,
+        subsets: config.get_bool("phrases.subsets").unwrap_or(false),
+        max_subset_run,
+        join_styles,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join(style: JoinStyle, words: &[&str]) -> String {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        style.join(&words.iter().collect::<Vec<&String>>())
+    }
+
+    #[test]
+    fn camel_case_keeps_the_inner_casing() {
+        assert_eq!(join(JoinStyle::Camel, &["Golden", "Gate"]), "GoldenGate");
+        assert_eq!(join(JoinStyle::Camel, &["golden", "gate"]), "GoldenGate");
+        assert_eq!(join(JoinStyle::Camel, &["Ronald", "McDonald"]), "RonaldMcDonald");
+        assert_eq!(join(JoinStyle::LowerCamel, &["Golden", "Gate"]), "goldenGate");
+        assert_eq!(join(JoinStyle::LowerCamel, &["McDonald", "Farms"]), "mcDonaldFarms");
+    }
+
+    #[test]
+    fn separators() {
+        assert_eq!(join(JoinStyle::Plain, &["Golden", "Gate"]), "GoldenGate");
+        assert_eq!(join(JoinStyle::Space, &["Golden", "Gate"]), "Golden Gate");
+        assert_eq!(join(JoinStyle::Underscore, &["Golden", "Gate"]), "Golden_Gate");
+        assert_eq!(join(JoinStyle::Hyphen, &["Golden", "Gate"]), "Golden-Gate");
+    }
+
+    #[test]
+    fn style_names() {
+        assert_eq!(JoinStyle::from_name("camel"), Some(JoinStyle::Camel));
+        assert_eq!(JoinStyle::from_name("LowerCamel"), Some(JoinStyle::LowerCamel));
+        assert_eq!(JoinStyle::from_name("kebab"), None);
+    }
+
+    #[test]
+    fn combinations_are_sorted_selections() {
+        assert_eq!(combinations(3, 2), vec![vec![0, 1], vec![0, 2], vec![1, 2]]);
+        assert_eq!(combinations(2, 3), Vec::<Vec<usize>>::new());
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+use config::Value;
+use reqwest::blocking::Response;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use url::Url;
+
+use super::client;
+use super::configuration;
+
+// how hard we're allowed to hit a single host
+#[derive(Clone, Copy, Debug)]
+pub struct HostLimits {
+    requests_per_second: f64,  // 0 or less is unlimited
+    burst: f64,
+    max_connections: usize,  // 0 is unlimited
+    respect_crawl_delay: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Robots {
+    Unchecked,
+    Pending,
+    Checked(Option<Duration>),
+}
+
+#[derive(Debug)]
+struct HostState {
+    limits: HostLimits,
+    tokens: f64,
+    last_refill: Instant,
+    active: usize,
+    blocked_until: Option<Instant>,
+    robots: Robots,
+}
+
+impl HostState {
+    fn new(limits: HostLimits) -> HostState {
+        HostState {
+            limits,
+            tokens: limits.burst,
+            last_refill: Instant::now(),
+            active: 0,
+            blocked_until: None,
+            robots: if limits.respect_crawl_delay { Robots::Unchecked } else { Robots::Checked(None) },
+        }
+    }
+
+    // a robots.txt Crawl-delay turns the bucket into one request every N seconds
+    fn rate_and_burst(&self) -> (f64, f64) {
+        match self.robots {
+            Robots::Checked(Some(delay)) if delay.as_secs_f64() > 0.0 => {
+                let delayed_rate = 1.0 / delay.as_secs_f64();
+                if self.limits.requests_per_second <= 0.0 || delayed_rate < self.limits.requests_per_second {
+                    (delayed_rate, 1.0)
+                } else {
+                    (self.limits.requests_per_second, self.limits.burst)
+                }
+            }
+            _ => (self.limits.requests_per_second, self.limits.burst)
+        }
+    }
+
+    // returns how long the caller has to wait before it may send a request, or None if it can go now
+    fn wait_time(&mut self, now: Instant) -> Option<Duration> {
+        if let Some(until) = self.blocked_until {
+            if until > now {
+                return Some(until - now);
+            }
+            self.blocked_until = None;
+        }
+
+        if self.limits.max_connections > 0 && self.active >= self.limits.max_connections {
+            // we get woken up when a connection is released, this is just a fallback
+            return Some(Duration::from_secs(1));
+        }
+
+        let (rate, burst) = self.rate_and_burst();
+        if rate <= 0.0 {
+            return None;
+        }
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst.max(1.0));
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+struct Hosts {
+    states: Mutex<HashMap<String, HostState>>,
+    overrides: Mutex<HashMap<String, HostLimits>>,
+    changed: Condvar,
+}
+
+lazy_static! {
+    static ref HOSTS: Hosts = Hosts {
+        states: Mutex::new(HashMap::new()),
+        overrides: Mutex::new(HashMap::new()),
+        changed: Condvar::new(),
+    };
+    static ref GLOBAL_LIMITS: HostLimits = read_global_limits();
+}
+
+// held for as long as a request to a host is in flight, releases the connection slot when dropped
+pub struct HostPermit {
+    host: Option<String>,
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        if let Some(host) = &self.host {
+            if let Ok(mut states) = HOSTS.states.lock() {
+                if let Some(state) = states.get_mut(host) {
+                    state.active = state.active.saturating_sub(1);
+                }
+            }
+            HOSTS.changed.notify_all();
+        }
+    }
+}
+
+// records the outcome of a robots.txt lookup when dropped, so the requests waiting on it are released
+// even if the lookup panics (it's then treated as having no crawl delay)
+struct RobotsCheck<'a> {
+    host: &'a str,
+    delay: Option<Duration>,
+}
+
+impl Drop for RobotsCheck<'_> {
+    fn drop(&mut self) {
+        let mut states = HOSTS.states.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(state) = states.get_mut(self.host) {
+            state.robots = Robots::Checked(self.delay);
+        }
+        HOSTS.changed.notify_all();
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    Url::parse(url).ok().and_then(|parsed| parsed.host_str().map(|host| host.to_lowercase()))
+}
+
+// blocks until the host of the URL may be sent another request
+pub fn acquire(url: &str) -> HostPermit {
+    let host = match host_of(url) {
+        Some(host) => host,
+        None => return HostPermit { host: None }
+    };
+
+    let mut states = HOSTS.states.lock().unwrap();
+    loop {
+        let state = states.entry(host.clone()).or_insert_with(|| HostState::new(limits_for(&host)));
+
+        match state.robots {
+            Robots::Unchecked => {
+                // the first request to a host looks up its crawl delay, everyone else waits for the answer
+                state.robots = Robots::Pending;
+                drop(states);
+                let mut check = RobotsCheck { host: &host, delay: None };
+                check.delay = fetch_crawl_delay(url);
+                drop(check);
+                states = HOSTS.states.lock().unwrap();
+                continue;
+            }
+            Robots::Pending => {
+                states = HOSTS.changed.wait(states).unwrap();
+                continue;
+            }
+            Robots::Checked(_) => ()
+        }
+
+        match state.wait_time(Instant::now()) {
+            None => {
+                state.tokens -= 1.0;
+                state.active += 1;
+                return HostPermit { host: Some(host) };
+            }
+            Some(wait) => {
+                if configuration::read_debug() {
+                    println!("~~~  Waiting {:.2}s before requesting from {}", wait.as_secs_f64(), host);
+                }
+                states = HOSTS.changed.wait_timeout(states, wait).unwrap().0;
+            }
+        }
+    }
+}
+
+// checks a response for a "Retry-After" and holds back every request to that host until it has passed
+pub fn observe_response(url: &str, response: &Response) {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS && response.status() != StatusCode::SERVICE_UNAVAILABLE {
+        return;
+    }
+    let delay = match response.headers().get(RETRY_AFTER).and_then(|value| value.to_str().ok()) {
+        Some(value) => match parse_retry_after(value) {
+            Some(delay) => delay,
+            None => return
+        },
+        None => return
+    };
+    let delay = delay.min(read_max_retry_after());
+    if let Some(host) = host_of(url) {
+        println!("!!!  {} asked us to back off for {}s", host, delay.as_secs());
+        let mut states = HOSTS.states.lock().unwrap();
+        let state = states.entry(host.clone()).or_insert_with(|| HostState::new(limits_for(&host)));
+        let until = Instant::now() + delay;
+        // never shorten a back-off that's already in place
+        let extends_block = match state.blocked_until {
+            Some(current) => current < until,
+            None => true
+        };
+        if extends_block {
+            state.blocked_until = Some(until);
+        }
+    }
+}
+
+// the header is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => match httpdate::parse_http_date(value.trim()) {
+            Ok(date) => Some(date.duration_since(SystemTime::now()).unwrap_or_else(|_| Duration::from_secs(0))),
+            Err(_) => None
+        }
+    }
+}
+
+fn fetch_crawl_delay(url: &str) -> Option<Duration> {
+    let robots_url = match Url::parse(url).and_then(|parsed| parsed.join("/robots.txt")) {
+        Ok(robots_url) => robots_url,
+        Err(_) => return None
+    };
+    let text = match client::CLIENT.get(robots_url.as_str()).send() {
+        Ok(response) if response.status().is_success() => match response.text() {
+            Ok(text) => text,
+            Err(_) => return None
+        },
+        _ => return None
+    };
+    let delay = parse_crawl_delay(&text);
+    if let Some(delay) = delay {
+        println!("###  {} has a Crawl-delay of {}s", robots_url, delay.as_secs_f64());
+    }
+    delay
+}
+
+// reads the Crawl-delay that applies to every user-agent ("*") from a robots.txt
+fn parse_crawl_delay(robots: &str) -> Option<Duration> {
+    let mut applies = false;
+    let mut in_agents = false;
+    for line in robots.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap_or("").trim().to_lowercase();
+        let value = parts.next().unwrap_or("").trim();
+        match key.as_str() {
+            "user-agent" => {
+                // consecutive user-agent lines share the same group
+                if !in_agents {
+                    applies = false;
+                }
+                in_agents = true;
+                if value == "*" {
+                    applies = true;
+                }
+            }
+            "crawl-delay" => {
+                in_agents = false;
+                if applies {
+                    if let Ok(seconds) = value.parse::<f64>() {
+                        if seconds >= 0.0 {
+                            return Some(Duration::from_secs_f64(seconds));
+                        }
+                    }
+                }
+            }
+            "" => (),
+            _ => in_agents = false
+        }
+    }
+    None
+}
+
+// lets an Engine's table override the global limits for the host it searches on
+pub fn set_host_limits(url: &str, table: &HashMap<String, Value>) {
+    if let Some(host) = host_of(url) {
+        let limits = limits_from_table(table, *GLOBAL_LIMITS);
+        HOSTS.overrides.lock().unwrap().insert(host, limits);
+    }
+}
+
+fn limits_for(host: &str) -> HostLimits {
+    match HOSTS.overrides.lock().unwrap().get(host) {
+        Some(limits) => *limits,
+        None => *GLOBAL_LIMITS
+    }
+}
+
+fn limits_from_table(table: &HashMap<String, Value>, defaults: HostLimits) -> HostLimits {
+    let float = |key: &str, default: f64| match table.get(key) {
+        Some(value) => value.clone().into_float().unwrap_or_else(|e| {
+            println!("!!!  \"{}\" isn't a valid number: {}", key, e);
+            default
+        }),
+        None => default
+    };
+    let max_connections = match table.get("max_connections_per_host") {
+        Some(value) => match value.clone().into_int() {
+            Ok(max) if max >= 0 => max as usize,
+            _ => {
+                println!("!!!  \"max_connections_per_host\" isn't a valid count");
+                defaults.max_connections
+            }
+        },
+        None => defaults.max_connections
+    };
+    let respect_crawl_delay = match table.get("respect_crawl_delay") {
+        Some(value) => value.clone().into_bool().unwrap_or(defaults.respect_crawl_delay),
+        None => defaults.respect_crawl_delay
+    };
+
+    HostLimits {
+        requests_per_second: float("requests_per_second", defaults.requests_per_second),
+        burst: float("burst", defaults.burst).max(1.0),
+        max_connections,
+        respect_crawl_delay,
+    }
+}
+
+fn read_global_limits() -> HostLimits {
+    let unlimited = HostLimits {
+        requests_per_second: 0.0,
+        burst: 1.0,
+        max_connections: 0,
+        respect_crawl_delay: false,
+    };
+    match configuration::CONFIGURATION.read().unwrap().get_table("politeness") {
+        Ok(table) => limits_from_table(&table, unlimited),
+        Err(_) => {
+            println!("!!!  The \"politeness\" table is missing from the configuration. Requests won't be throttled");
+            unlimited
+        }
+    }
+}
+
+fn read_max_retry_after() -> Duration {
+    match configuration::CONFIGURATION.read().unwrap().get_int("politeness.max_retry_after") {
+        Ok(seconds) if seconds >= 0 => Duration::from_secs(seconds as u64),
+        _ => Duration::from_secs(120)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+
+    fn limits(requests_per_second: f64, burst: f64, max_connections: usize) -> HostLimits {
+        HostLimits {
+            requests_per_second,
+            burst,
+            max_connections,
+            respect_crawl_delay: true,
+        }
+    }
+
+    #[test]
+    fn crawl_delay_for_every_agent() {
+        let robots = "User-agent: Googlebot\nCrawl-delay: 1\n\nUser-agent: *\nDisallow: /private\nCrawl-delay: 2.5 # be nice\n";
+        assert_eq!(parse_crawl_delay(robots), Some(Duration::from_millis(2500)));
+        // consecutive user-agent lines share one group
+        assert_eq!(parse_crawl_delay("User-agent: Bingbot\nUser-agent: *\nCrawl-delay: 3"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_crawl_delay("USER-AGENT: *\nCRAWL-DELAY: 4"), Some(Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn crawl_delay_for_other_agents_is_ignored() {
+        assert_eq!(parse_crawl_delay("User-agent: Googlebot\nCrawl-delay: 1\n"), None);
+        assert_eq!(parse_crawl_delay("User-agent: *\nCrawl-delay: soon\n"), None);
+        assert_eq!(parse_crawl_delay("User-agent: *\nCrawl-delay: -1\n"), None);
+        assert_eq!(parse_crawl_delay(""), None);
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::from_secs(0)));
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_retry_after("-5"), None);
+    }
+
+    #[test]
+    fn retry_after_as_a_date() {
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(60));
+        let delay = parse_retry_after(&later).unwrap();
+        // the date only has whole seconds
+        assert!(delay > Duration::from_secs(58) && delay <= Duration::from_secs(60), "{:?}", delay);
+        // a date that has already passed doesn't hold anything back
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn wait_time_spends_the_burst_then_refills() {
+        let start = Instant::now();
+        let mut state = HostState::new(limits(2.0, 2.0, 0));
+        state.robots = Robots::Checked(None);
+        state.last_refill = start;
+        for _ in 0..2 {
+            assert_eq!(state.wait_time(start), None);
+            state.tokens -= 1.0;
+        }
+        assert_eq!(state.wait_time(start), Some(Duration::from_millis(500)));
+        assert_eq!(state.wait_time(start + Duration::from_millis(500)), None);
+    }
+
+    #[test]
+    fn wait_time_follows_the_crawl_delay() {
+        let start = Instant::now();
+        let mut state = HostState::new(limits(10.0, 4.0, 0));
+        state.robots = Robots::Checked(Some(Duration::from_secs(2)));
+        state.tokens = 0.0;
+        state.last_refill = start;
+        assert_eq!(state.wait_time(start), Some(Duration::from_secs(2)));
+        // the bucket only holds a single request under a crawl delay
+        assert_eq!(state.wait_time(start + Duration::from_secs(60)), None);
+        assert_eq!(state.tokens, 1.0);
+    }
+
+    #[test]
+    fn wait_time_respects_blocks_and_connections() {
+        let start = Instant::now();
+        let mut state = HostState::new(limits(0.0, 1.0, 1));
+        state.robots = Robots::Checked(None);
+        state.blocked_until = Some(start + Duration::from_secs(30));
+        assert_eq!(state.wait_time(start), Some(Duration::from_secs(30)));
+        assert_eq!(state.wait_time(start + Duration::from_secs(30)), None);
+        assert_eq!(state.blocked_until, None);
+
+        state.active = 1;
+        assert!(state.wait_time(start).is_some());
+        state.active = 0;
+        assert_eq!(state.wait_time(start), None);
+    }
+
+    #[test]
+    fn panicking_robots_lookup_releases_waiters() {
+        let host = "panicking-robots.invalid";
+        let mut state = HostState::new(limits(0.0, 1.0, 0));
+        state.robots = Robots::Pending;
+        HOSTS.states.lock().unwrap().insert(host.to_string(), state);
+
+        let result = panic::catch_unwind(|| {
+            let _check = RobotsCheck { host, delay: None };
+            panic!("robots.txt lookup crashed");
+        });
+        assert!(result.is_err());
+        assert_eq!(HOSTS.states.lock().unwrap()[host].robots, Robots::Checked(None));
+    }
+}
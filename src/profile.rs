@@ -0,0 +1,331 @@
+use std::collections::HashSet;
+use config::Config;
+
+use super::configuration;
+use super::dedup;
+
+// the keys of a profile file that hold words about the target, in the order their candidates come out
+const WORD_KEYS: [&str; 9] = [
+    "names",
+    "nicknames",
+    "partner_names",
+    "children_names",
+    "pet_names",
+    "company",
+    "city",
+    "teams",
+    "keywords",
+];
+
+// the keys of a profile file that hold dates, as "YYYY-MM-DD" or "YYYYMMDD"
+const DATE_KEYS: [&str; 4] = [
+    "birthdates",
+    "partner_birthdates",
+    "children_birthdates",
+    "anniversaries",
+];
+
+// what we know about the target from a profile file (see profile.example.toml)
+#[derive(Clone, Debug)]
+pub struct Profile {
+    words: Vec<String>,  // with spaces removed, e.g. "New York" is "NewYork"
+    numbers: Vec<String>,  // the pieces of every date (e.g. "1949", "49", "0917") and any favorite numbers
+}
+
+// how the profile is combined with the scraped words
+#[derive(Clone, Debug)]
+pub struct ProfileSettings {
+    top_critical_words: usize,
+    max_candidates: usize,
+}
+
+// the profile in "profile.file" (set by --profile), if there is one
+pub fn read_profile() -> Option<Profile> {
+    let filename = match configuration::CONFIGURATION.read().unwrap().get_str("profile.file") {
+        Ok(filename) => filename,
+        Err(_) => return None
+    };
+
+    println!("###  Loading target profile: {}", filename);
+    let mut file = Config::new();
+    if let Err(e) = file.merge(config::File::with_name(filename.as_str())) {
+        println!("!!!  Target profile \"{}\" could not be loaded: {}", filename, e);
+        return None;
+    }
+
+    let mut words = Vec::new();
+    for key in WORD_KEYS.iter() {
+        for value in read_strings(&file, key) {
+            let word: String = value.split_whitespace().collect();
+            if !word.is_empty() {
+                words.push(word);
+            }
+        }
+    }
+
+    let mut numbers = Vec::new();
+    for key in DATE_KEYS.iter() {
+        for date in read_strings(&file, key) {
+            match date_parts(date.as_str()) {
+                Some(parts) => numbers.extend(parts),
+                None => println!("!!!  \"{}\" in \"{}\" isn't a date like \"YYYY-MM-DD\" or \"YYYYMMDD\" and will be ignored", date, key)
+            }
+        }
+    }
+    numbers.extend(read_strings(&file, "numbers"));
+
+    if words.is_empty() {
+        println!("!!!  Target profile \"{}\" doesn't have any names or words in it", filename);
+        return None;
+    }
+    dedup::dedup_case_insensitive(&mut words);
+    dedup::dedup_exact(&mut numbers);
+
+    Some(Profile {
+        words,
+        numbers,
+    })
+}
+
+pub fn read_settings() -> ProfileSettings {
+    let config = configuration::CONFIGURATION.read().unwrap();
+    ProfileSettings {
+        top_critical_words: match config.get_int("profile.top_critical_words") {
+            Ok(value) if value >= 0 => value as usize,
+            _ => 20
+        },
+        max_candidates: match config.get_int("profile.max_candidates") {
+            Ok(value) if value >= 0 => value as usize,
+            _ => 100000
+        },
+    }
+}
+
+// a single string is allowed anywhere a list is, since most people only have one birthdate
+fn read_strings(file: &Config, key: &str) -> Vec<String> {
+    if let Ok(values) = file.get_array(key) {
+        return values.into_iter().filter_map(|value| value.into_str().ok()).collect();
+    }
+    match file.get_str(key) {
+        Ok(value) => vec![value],
+        Err(_) => Vec::new()
+    }
+}
+
+// every way a date shows up in a password, e.g. "1949-09-17" gives "1949", "49", "0917", "1709", "170949", ...
+// the date can also be written "1949/09/17", "1949.09.17" or "19490917"
+fn date_parts(date: &str) -> Option<Vec<String>> {
+    let date = date.trim();
+    let pieces: Vec<&str> = if date.len() == 8 && date.is_char_boundary(4) && date.is_char_boundary(6) {
+        vec![&date[..4], &date[4..6], &date[6..]]
+    } else {
+        date.split(['-', '/', '.']).collect()
+    };
+    if pieces.len() != 3 || pieces[0].len() != 4 || pieces[1].len() != 2 || pieces[2].len() != 2
+        || !pieces.iter().all(|piece| piece.chars().all(|character| character.is_ascii_digit())) {
+        return None;
+    }
+    let (year, month, day) = (pieces[0], pieces[1], pieces[2]);
+    if !(1..=12).contains(&month.parse::<u32>().unwrap_or(0)) || !(1..=31).contains(&day.parse::<u32>().unwrap_or(0)) {
+        return None;
+    }
+    let short_year = &year[2..];
+    let short_month = month.trim_start_matches('0');
+    let short_day = day.trim_start_matches('0');
+
+    Some(vec![
+        year.to_string(),
+        short_year.to_string(),
+        format!("{}{}", month, day),
+        format!("{}{}", day, month),
+        format!("{}{}", short_month, short_day),
+        format!("{}{}{}", day, month, short_year),
+        format!("{}{}{}", month, day, short_year),
+        format!("{}{}{}", day, month, year),
+        format!("{}{}{}", month, day, year),
+        format!("{}{}{}", year, month, day),
+        month.to_string(),
+        day.to_string(),
+    ])
+}
+
+fn capitalize(word: &str) -> String {
+    let mut characters = word.chars();
+    match characters.next() {
+        Some(first) => first.to_uppercase().chain(characters.as_str().to_lowercase().chars()).collect(),
+        None => String::new()
+    }
+}
+
+// the lowercase and capitalized spelling of a word, the way people usually type it
+fn spellings(word: &str) -> Vec<String> {
+    let lower = word.to_lowercase();
+    let capitalized = capitalize(word);
+    if lower == capitalized {
+        vec![lower]
+    } else {
+        vec![capitalized, lower]
+    }
+}
+
+// CUPP-style candidates from the profile, and from the profile combined with the best scraped critical words
+// they're generated in order of how likely they are, so the budget cuts off the least likely ones
+pub fn generate(profile: &Profile, critical_words: &[String], settings: &ProfileSettings) -> Vec<String> {
+    let mut candidates = Candidates {
+        list: Vec::new(),
+        seen: HashSet::new(),
+        max: settings.max_candidates,
+    };
+    let scraped: Vec<&String> = critical_words.iter().take(settings.top_critical_words).collect();
+
+    // the words on their own, then with a date or number ("Wile1949", "coyote0917")
+    for word in &profile.words {
+        for spelling in spellings(word) {
+            candidates.push(spelling.clone());
+        }
+    }
+    for word in &profile.words {
+        for spelling in spellings(word) {
+            for number in &profile.numbers {
+                candidates.push(format!("{}{}", spelling, number));
+                candidates.push(format!("{}{}", number, spelling));
+            }
+        }
+    }
+
+    // two of the words together ("WileCoyote", "acmewile"), with and without a date or number
+    for first in &profile.words {
+        for second in &profile.words {
+            if first == second {
+                continue;
+            }
+            let pairs = vec![capitalize(first) + &capitalize(second), first.to_lowercase() + &second.to_lowercase()];
+            for pair in pairs {
+                candidates.push(pair.clone());
+                for number in &profile.numbers {
+                    candidates.push(format!("{}{}", pair, number));
+                }
+            }
+        }
+    }
+
+    // the best scraped words with the profile's numbers and words ("Rockets1949", "WileRockets")
+    for critical in &scraped {
+        for number in &profile.numbers {
+            candidates.push(format!("{}{}", critical, number));
+        }
+        for word in &profile.words {
+            candidates.push(format!("{}{}", capitalize(word), critical));
+            candidates.push(format!("{}{}", critical, capitalize(word)));
+        }
+    }
+
+    if candidates.list.len() >= candidates.max {
+        println!("!!!  The target profile produced more than {} candidates, the least likely were left out", candidates.max);
+    }
+    candidates.list
+}
+
+// the candidates so far, which stop growing once there are enough of them
+struct Candidates {
+    list: Vec<String>,
+    seen: HashSet<String>,
+    max: usize,
+}
+
+impl Candidates {
+    fn push(&mut self, candidate: String) {
+        if self.list.len() < self.max && self.seen.insert(candidate.clone()) {
+            self.list.push(candidate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    fn profile(words: &[&str], numbers: &[&str]) -> Profile {
+        Profile {
+            words: strings(words),
+            numbers: strings(numbers),
+        }
+    }
+
+    #[test]
+    fn iso_dates() {
+        assert_eq!(date_parts("1949-09-17"), Some(strings(&[
+            "1949", "49", "0917", "1709", "917", "170949", "091749", "17091949", "09171949", "19490917", "09", "17",
+        ])));
+        assert_eq!(date_parts(" 2001-10-05 "), date_parts("2001-10-05"));
+    }
+
+    #[test]
+    fn numeric_dates() {
+        assert_eq!(date_parts("19490917"), date_parts("1949-09-17"));
+        assert_eq!(date_parts("1949/09/17"), date_parts("1949-09-17"));
+        assert_eq!(date_parts("1949.09.17"), date_parts("1949-09-17"));
+    }
+
+    #[test]
+    fn invalid_dates() {
+        assert_eq!(date_parts(""), None);
+        assert_eq!(date_parts("September 17th"), None);
+        assert_eq!(date_parts("49-09-17"), None);
+        assert_eq!(date_parts("1949-9-17"), None);
+        assert_eq!(date_parts("1949-09-17-01"), None);
+        assert_eq!(date_parts("1949-13-01"), None);
+        assert_eq!(date_parts("1949-00-10"), None);
+        assert_eq!(date_parts("1949-09-32"), None);
+        assert_eq!(date_parts("194909170"), None);
+        assert_eq!(date_parts("1949O917"), None);
+        assert_eq!(date_parts("19é90917"), None);
+    }
+
+    #[test]
+    fn case_spellings() {
+        assert_eq!(spellings("wile"), strings(&["Wile", "wile"]));
+        assert_eq!(spellings("COYOTE"), strings(&["Coyote", "coyote"]));
+        assert_eq!(spellings("McDonald"), strings(&["Mcdonald", "mcdonald"]));
+        assert_eq!(spellings("émile"), strings(&["Émile", "émile"]));
+        assert_eq!(spellings("1949"), strings(&["1949"]));
+    }
+
+    #[test]
+    fn candidates_combine_words_numbers_and_scraped_words() {
+        let settings = ProfileSettings {
+            top_critical_words: 1,
+            max_candidates: 1000,
+        };
+        let candidates = generate(&profile(&["Wile", "acme"], &["49"]), &strings(&["Rockets", "Anvils"]), &settings);
+        for expected in &["Wile", "wile", "Acme", "Wile49", "49wile", "WileAcme", "acmewile49", "Rockets49", "WileRockets",
+                          "RocketsAcme"] {
+            assert!(candidates.contains(&expected.to_string()), "{} is missing", expected);
+        }
+        // only the top scraped words are used
+        assert!(!candidates.iter().any(|candidate| candidate.contains("Anvils")));
+        let unique: HashSet<&String> = candidates.iter().collect();
+        assert_eq!(unique.len(), candidates.len());
+    }
+
+    #[test]
+    fn budget_keeps_the_most_likely_candidates() {
+        let settings = ProfileSettings {
+            top_critical_words: 20,
+            max_candidates: 5,
+        };
+        let target = profile(&["Wile", "Coyote"], &["1949", "49"]);
+        let candidates = generate(&target, &strings(&["Rockets"]), &settings);
+        assert_eq!(candidates, strings(&["Wile", "wile", "Coyote", "coyote", "Wile1949"]));
+
+        let settings = ProfileSettings {
+            top_critical_words: 20,
+            max_candidates: 0,
+        };
+        assert!(generate(&target, &[], &settings).is_empty());
+    }
+}
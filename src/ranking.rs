@@ -0,0 +1,61 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use super::dedup;
+
+// how often, on how many pages, and in how important a place a word was found, in any case
+#[derive(Clone, Debug)]
+pub struct WordStats {
+    pub spelling: String,  // the first spelling found, which is the one written to the wordlist
+    pub occurrences: usize,
+    pub pages: usize,
+    pub best_weight: f64,
+}
+
+// how many points a word gets toward its score for each page it's on and each time it's found
+pub struct RankingPoints {
+    pub page: f64,
+    pub occurrence: f64,
+}
+
+impl RankingPoints {
+    // words found on many pages, many times, in important places come first
+    pub fn score(&self, stats: &WordStats) -> f64 {
+        stats.best_weight * (self.page * stats.pages as f64 + self.occurrence * stats.occurrences as f64)
+    }
+}
+
+// takes every page's words along with how much each one weighs, and returns them the highest scoring first
+// "Rocket", "rocket" and "ROCKET" are the same word, so they're counted together under the first spelling found
+pub fn rank<P, W>(pages: P, points: &RankingPoints) -> Vec<WordStats>
+    where P: IntoIterator<Item = W>, W: IntoIterator<Item = (String, f64)> {
+    let mut stats: HashMap<String, WordStats> = HashMap::new();
+    let mut ranked: Vec<String> = Vec::new();
+
+    for page in pages {
+        let mut seen_on_page: HashSet<String> = HashSet::new();
+        for (text, weight) in page {
+            let folded = dedup::fold_case(text.as_str());
+            if !stats.contains_key(&folded) {
+                ranked.push(folded.clone());
+                stats.insert(folded.clone(), WordStats { spelling: text, occurrences: 0, pages: 0, best_weight: weight });
+            }
+            let word_stats = stats.get_mut(&folded).unwrap();
+            word_stats.occurrences += 1;
+            // a word counts for the most important place it was found
+            if weight > word_stats.best_weight {
+                word_stats.best_weight = weight;
+            }
+            if seen_on_page.insert(folded) {
+                word_stats.pages += 1;
+            }
+        }
+    }
+
+    // the sort is stable, so words with the same score stay in the order they were found
+    ranked.sort_by(|first, second| {
+        points.score(&stats[second]).partial_cmp(&points.score(&stats[first])).unwrap_or(Ordering::Equal)
+    });
+
+    ranked.into_iter().map(|word| stats.remove(&word).unwrap()).collect()
+}
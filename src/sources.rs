@@ -0,0 +1,492 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use config::Value;
+use regex::Regex;
+use serde_json::Value as Json;
+use url::Url;
+
+use super::client;
+use super::client::FetchError;
+use super::configuration;
+use super::engine;
+use super::engine::{SearchResult, SearchSource};
+use super::entities;
+
+lazy_static! {
+    static ref WORD_REGEX: Regex = Regex::new(r"[a-zA-Z0-9]+").unwrap();
+    static ref LOC_REGEX: Regex = Regex::new(r"(?s)<loc>\s*(.*?)\s*</loc>").unwrap();
+}
+
+// the most characters of a file's matching line that are kept as its snippet
+const MAX_SNIPPET_LENGTH: usize = 300;
+
+// the words in a query, lowercased, that a native source looks for
+fn query_words(query: &str) -> Vec<String> {
+    WORD_REGEX.find_iter(query).map(|word| word.as_str().to_lowercase()).collect()
+}
+
+fn read_string(table: &HashMap<String, Value>, key: &str) -> Option<String> {
+    table.get(key).and_then(|value| value.clone().into_str().ok())
+}
+
+fn read_count(table: &HashMap<String, Value>, key: &str, default: usize) -> usize {
+    match table.get(key).map(|value| value.clone().into_int()) {
+        Some(Ok(value)) if value >= 0 => value as usize,
+        Some(_) => {
+            println!("!!!  \"{}\" isn't a valid count, defaulting to {}", key, default);
+            default
+        }
+        None => default
+    }
+}
+
+// the results a native source found, cut down to "sensitivity.max_links"
+fn limit_links(source: &dyn SearchSource, links: &mut Vec<SearchResult>) {
+    if let Some(max) = engine::read_max_links() {
+        if max < links.len() {
+            println!("###  {} received {} links but will only return the first {}", source, links.len(), max);
+            links.truncate(max);
+        }
+    }
+}
+
+// searches the text files in a directory, e.g. notes or documents already gathered about the target
+// a file matches when it has every word in the query, and the files with the most matches come first
+#[derive(Debug)]
+pub struct FileIndex {
+    name: String,
+    directory: PathBuf,
+    extensions: Vec<String>,
+}
+
+impl FileIndex {
+    pub fn from_table(name: String, table: &HashMap<String, Value>) -> FileIndex {
+        let directory = match read_string(table, "directory") {
+            Some(directory) => PathBuf::from(directory),
+            None => panic!("!!!  The \"{}\" engine is a file index without a \"directory\"", name)
+        };
+        let extensions = match table.get("extensions").map(|value| value.clone().into_array()) {
+            Some(Ok(values)) => values.into_iter()
+                .filter_map(|value| value.into_str().ok())
+                .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                .collect(),
+            _ => vec!["txt", "md", "html", "htm"].into_iter().map(String::from).collect()
+        };
+        client::allow_local_directory(&directory);
+        FileIndex {
+            name,
+            directory,
+            extensions,
+        }
+    }
+
+    // every file under the directory with one of the extensions
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        // symlinked directories are followed, but each directory is only read once so a link back up can't loop forever
+        let mut visited = HashSet::new();
+        let mut directories = vec![self.directory.clone()];
+        while let Some(directory) = directories.pop() {
+            match fs::canonicalize(&directory) {
+                Ok(canonical) => if !visited.insert(canonical) {
+                    continue;
+                },
+                Err(e) => {
+                    println!("!!!  {} could not read \"{}\": {}", self, directory.display(), e);
+                    continue;
+                }
+            }
+            let entries = match fs::read_dir(&directory) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    println!("!!!  {} could not read \"{}\": {}", self, directory.display(), e);
+                    continue;
+                }
+            };
+            for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+                if path.is_dir() {
+                    directories.push(path);
+                } else if self.has_extension(&path) {
+                    files.push(path);
+                }
+            }
+        }
+        // read_dir doesn't promise any order
+        files.sort();
+
+        files
+    }
+
+    fn has_extension(&self, path: &Path) -> bool {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => self.extensions.contains(&extension.to_lowercase()),
+            None => false
+        }
+    }
+}
+
+impl fmt::Display for FileIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "File index \"{}\"", self.directory.display())
+    }
+}
+
+impl SearchSource for FileIndex {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    // the files' own paths aren't about the query, so the link QA check is skipped
+    fn search(&self, query: &str) -> Result<Vec<SearchResult>, FetchError> {
+        println!("###  {} dispatched with query: {}", self, query);
+        let words = query_words(query);
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut matches = Vec::new();
+        for path in self.files() {
+            let text = match fs::read(&path) {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(e) => {
+                    println!("!!!  {} could not read \"{}\": {}", self, path.display(), e);
+                    continue;
+                }
+            };
+            let lower = text.to_lowercase();
+            if !words.iter().all(|word| lower.contains(word.as_str())) {
+                continue;
+            }
+            let count: usize = words.iter().map(|word| lower.matches(word.as_str()).count()).sum();
+
+            // the Fetcher reads file URLs from disk, so the file's words end up in the wordlist like a page's
+            let link = match fs::canonicalize(&path).ok().and_then(|absolute| Url::from_file_path(absolute).ok()) {
+                Some(url) => url.to_string(),
+                None => continue
+            };
+            let snippet = text.lines()
+                .find(|line| {
+                    let line = line.to_lowercase();
+                    words.iter().any(|word| line.contains(word.as_str()))
+                })
+                .map(|line| line.trim().chars().take(MAX_SNIPPET_LENGTH).collect::<String>());
+            matches.push((count, SearchResult {
+                link,
+                title: path.file_stem().and_then(|stem| stem.to_str()).map(|stem| stem.replace(&['_', '-'][..], " ")),
+                snippet,
+            }));
+        }
+
+        // the sort is stable, so files with the same count stay in path order
+        matches.sort_by_key(|(count, _)| Reverse(*count));
+        let mut links: Vec<SearchResult> = matches.into_iter().map(|(_, result)| result).collect();
+        limit_links(self, &mut links);
+
+        Ok(links)
+    }
+}
+
+// the pages in a site's sitemap whose URLs have the query's words in them, for sites without a search of their own
+// sitemap indexes are followed, and the URLs are only downloaded once however many queries there are
+#[derive(Debug)]
+pub struct Sitemap {
+    name: String,
+    url: String,
+    max_sitemaps: usize,
+    urls: Mutex<Option<Vec<String>>>,
+}
+
+impl Sitemap {
+    pub fn from_table(name: String, table: &HashMap<String, Value>) -> Sitemap {
+        let url = match read_string(table, "url") {
+            Some(url) => url,
+            None => panic!("!!!  The \"{}\" engine is a sitemap without a \"url\"", name)
+        };
+        Sitemap {
+            name,
+            url,
+            max_sitemaps: read_count(table, "max_sitemaps", 10),
+            urls: Mutex::new(None),
+        }
+    }
+
+    // every page URL in the sitemap, and in the sitemaps it points to
+    fn read_urls(&self) -> Result<Vec<String>, FetchError> {
+        let mut urls = Vec::new();
+        let mut sitemaps = vec![self.url.clone()];
+        let mut fetched = 0;
+        while let Some(sitemap) = sitemaps.pop() {
+            if fetched >= self.max_sitemaps {
+                println!("!!!  {} points to more than {} sitemaps, the rest were skipped", self, self.max_sitemaps);
+                break;
+            }
+            fetched += 1;
+
+            let text = match client::get_page(sitemap.as_str()) {
+                Ok(page) => page.text,
+                // the sitemap we were given failing is an error, but one it points to failing isn't
+                Err(e) if fetched == 1 => return Err(e),
+                Err(e) => {
+                    println!("!!!  {} could not get \"{}\": {}", self, sitemap, e);
+                    continue;
+                }
+            };
+            let (is_index, locations) = parse_sitemap(text.as_str());
+            if is_index {
+                sitemaps.extend(locations);
+            } else {
+                urls.extend(locations);
+            }
+        }
+        if configuration::read_debug() {
+            println!("~~~  {} has {} URLs from {} sitemaps", self, urls.len(), fetched);
+        }
+
+        Ok(urls)
+    }
+}
+
+// whether a sitemap is an index of other sitemaps, and the URLs in it
+// the sitemap is someone else's content, so only web pages are kept
+fn parse_sitemap(text: &str) -> (bool, Vec<String>) {
+    let locations = LOC_REGEX.captures_iter(text)
+        .filter_map(|cap| engine::web_link(entities::decode_entities(&cap[1]).as_str()))
+        .collect();
+    (text.contains("<sitemapindex"), locations)
+}
+
+impl fmt::Display for Sitemap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sitemap \"{}\"", self.url)
+    }
+}
+
+impl SearchSource for Sitemap {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<SearchResult>, FetchError> {
+        println!("###  {} dispatched with query: {}", self, query);
+        let mut cached = self.urls.lock().unwrap();
+        if cached.is_none() {
+            *cached = Some(self.read_urls()?);
+        }
+
+        let words = query_words(query);
+        let mut links: Vec<SearchResult> = cached.as_ref().unwrap().iter()
+            .filter(|url| {
+                let path = Url::parse(url.as_str()).map(|parsed| parsed.path().to_lowercase()).unwrap_or_default();
+                let path_words: Vec<&str> = WORD_REGEX.find_iter(path.as_str()).map(|word| word.as_str()).collect();
+                words.iter().any(|word| path_words.contains(&word.as_str()))
+            })
+            .map(|url| SearchResult {
+                link: url.clone(),
+                title: None,
+                snippet: None,
+            })
+            .collect();
+        // the URLs are all we know about the pages, so they get the same QA check as an engine's links
+        engine::filter_links(&mut links, query);
+        limit_links(self, &mut links);
+
+        Ok(links)
+    }
+}
+
+// a MediaWiki site's search API (Wikipedia, Fandom, or a company's internal wiki), with its titles and snippets
+// its "opensearch" title completions are the suggestions when queries are expanded
+#[derive(Debug)]
+pub struct MediaWiki {
+    name: String,
+    api: Url,
+    limit: usize,
+}
+
+impl MediaWiki {
+    pub fn from_table(name: String, table: &HashMap<String, Value>) -> MediaWiki {
+        let api = match read_string(table, "url").map(|url| Url::parse(url.as_str())) {
+            Some(Ok(api)) => api,
+            Some(Err(e)) => panic!("!!!  The \"{}\" engine's \"url\" isn't a valid URL: {}", name, e),
+            None => panic!("!!!  The \"{}\" engine is a MediaWiki without a \"url\" (its api.php)", name)
+        };
+        MediaWiki {
+            name,
+            api,
+            limit: read_count(table, "limit", 10).max(1),
+        }
+    }
+
+    // the API's JSON response for these parameters
+    fn call(&self, parameters: &[(&str, &str)]) -> Result<Json, FetchError> {
+        let mut url = self.api.clone();
+        url.query_pairs_mut()
+            .append_pair("format", "json")
+            .extend_pairs(parameters.iter());
+        if configuration::read_debug() {
+            println!("~~~  {} calling {}", self, url);
+        }
+
+        let text = client::get_page(url.as_str())?.text;
+        serde_json::from_str(text.as_str()).map_err(|e| FetchError::Body(format!("the response isn't valid JSON: {}", e)))
+    }
+}
+
+impl fmt::Display for MediaWiki {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MediaWiki \"{}\"", self.api)
+    }
+}
+
+impl SearchSource for MediaWiki {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    // the wiki's own search decides what's relevant, and its links don't have the title in them, so there's no QA check
+    fn search(&self, query: &str) -> Result<Vec<SearchResult>, FetchError> {
+        println!("###  {} dispatched with query: {}", self, query);
+        let limit = self.limit.to_string();
+        let response = self.call(&[
+            ("action", "query"),
+            ("list", "search"),
+            ("srprop", "snippet"),
+            ("srlimit", limit.as_str()),
+            ("srsearch", query),
+        ])?;
+
+        let mut links = Vec::new();
+        for result in response["query"]["search"].as_array().cloned().unwrap_or_default() {
+            // "index.php?curid=" works on every wiki, whatever its article path is
+            let link = match result["pageid"].as_u64().and_then(|id| self.api.join(format!("index.php?curid={}", id).as_str()).ok()) {
+                Some(link) => link.to_string(),
+                None => continue
+            };
+            links.push(SearchResult {
+                link,
+                title: result["title"].as_str().map(engine::clean_capture),
+                // the snippet highlights the matches with <span> tags
+                snippet: result["snippet"].as_str().map(engine::clean_capture),
+            });
+        }
+        limit_links(self, &mut links);
+
+        Ok(links)
+    }
+
+    fn suggests(&self) -> bool {
+        true
+    }
+
+    fn suggest(&self, query: &str) -> Result<Vec<String>, FetchError> {
+        let limit = self.limit.to_string();
+        let response = self.call(&[
+            ("action", "opensearch"),
+            ("limit", limit.as_str()),
+            ("search", query),
+        ])?;
+
+        // [query, [titles], [descriptions], [urls]]
+        Ok(response[1].as_array()
+            .map(|titles| titles.iter().filter_map(|title| title.as_str()).map(String::from).collect())
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory under the system's temp directory, removed when the test is done with it
+    struct TempDirectory(PathBuf);
+
+    impl TempDirectory {
+        fn new(name: &str) -> TempDirectory {
+            let path = std::env::temp_dir().join(format!("heated_words_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDirectory(path)
+        }
+    }
+
+    impl Drop for TempDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn index(directory: &Path) -> FileIndex {
+        FileIndex {
+            name: "notes".to_string(),
+            directory: directory.to_path_buf(),
+            extensions: vec!["txt".to_string(), "md".to_string()],
+        }
+    }
+
+    #[test]
+    fn file_index_finds_matching_files_under_the_directory() {
+        let temp = TempDirectory::new("file_index");
+        fs::create_dir_all(temp.0.join("team")).unwrap();
+        fs::write(temp.0.join("acme_rockets.txt"), "Acme rockets\nThe Acme rocket skates, more rockets").unwrap();
+        fs::write(temp.0.join("team").join("roster.MD"), "Wile E. Coyote builds rockets for Acme").unwrap();
+        fs::write(temp.0.join("anvils.txt"), "Acme anvils").unwrap();
+        fs::write(temp.0.join("rockets.pdf"), "Acme rockets").unwrap();
+
+        let source = index(&temp.0);
+        let names: Vec<String> = source.files().iter()
+            .map(|path| path.strip_prefix(&temp.0).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        assert_eq!(names, vec!["acme_rockets.txt", "anvils.txt", "team/roster.MD"]);
+
+        let results = source.search("acme rockets").unwrap();
+        assert_eq!(results.len(), 2);
+        // the file with the most matches comes first
+        assert!(results[0].link.starts_with("file://") && results[0].link.ends_with("acme_rockets.txt"));
+        assert_eq!(results[0].title.as_deref(), Some("acme rockets"));
+        assert_eq!(results[0].snippet.as_deref(), Some("Acme rockets"));
+        assert!(results[1].link.ends_with("roster.MD"));
+        assert!(source.search("  ").unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_index_survives_symlink_cycles() {
+        let temp = TempDirectory::new("file_index_cycle");
+        fs::create_dir_all(temp.0.join("notes")).unwrap();
+        fs::write(temp.0.join("notes").join("acme.txt"), "Acme").unwrap();
+        std::os::unix::fs::symlink(&temp.0, temp.0.join("notes").join("up")).unwrap();
+
+        let files = index(&temp.0).files();
+        assert_eq!(files, vec![temp.0.join("notes").join("acme.txt")]);
+    }
+
+    #[test]
+    fn sitemaps_and_their_indexes() {
+        let sitemap = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://acme.example/products/rocket-skates</loc><lastmod>2020-01-02</lastmod></url>
+  <url>
+    <loc>
+      https://acme.example/search?q=anvils&amp;page=2
+    </loc>
+  </url>
+  <url><loc>file:///etc/passwd</loc></url>
+  <url><loc>javascript:alert(1)</loc></url>
+</urlset>"#;
+        assert_eq!(parse_sitemap(sitemap), (false, vec![
+            "https://acme.example/products/rocket-skates".to_string(),
+            "https://acme.example/search?q=anvils&page=2".to_string(),
+        ]));
+
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>http://acme.example/sitemap1.xml</loc></sitemap>
+</sitemapindex>"#;
+        assert_eq!(parse_sitemap(index), (true, vec!["http://acme.example/sitemap1.xml".to_string()]));
+        assert_eq!(parse_sitemap("not a sitemap"), (false, Vec::new()));
+    }
+}
@@ -1,35 +1,91 @@
+use std::panic;
+use std::panic::AssertUnwindSafe;
 use std::sync::{mpsc, Arc, Mutex};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
+use std::thread::JoinHandle;
 use std::fs::File;
 use std::io::Write;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use url::Url;
 
+use super::client::FetchError;
 use super::configuration;
-use super::fetcher::Fetcher;
+use super::fetcher::{Fetcher, FetcherMessage};
+use super::parser::Word;
+
+// how far away from the original page a crawled link is allowed to wander
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CrawlScope {
+    Domain,  // same host as the page the crawl started from
+    Path,  // same host and under the same directory as the page the crawl started from
+    Any,
+}
+
+// the words one page produced
+#[derive(Clone, Debug)]
+pub struct PageWords {
+    pub url: String,
+    pub seed: String,  // the link the Spider was given that led to this page
+    pub engine: Option<String>,  // the engine that found the page (or the page its crawl started from)
+    pub words: Vec<Word>,
+}
 
 #[derive(Debug)]
 pub struct Spider {
-    link_vector: Vec<String>
+    link_vector: Vec<(String, Option<String>)>,  // each URL and the engine that found it
+    max_depth: u32,
+    scope: CrawlScope,
+    max_pages: Option<usize>,
+    workers: usize,
+    visited: HashSet<String>,
+    pages: Vec<String>,
+    final_urls: HashMap<String, String>,
+    failures: Vec<(String, FetchError)>,
 }
 
 impl Spider {
-    pub fn new(link_vector: Vec<String>) -> Spider {
+    pub fn new(link_vector: Vec<(String, Option<String>)>) -> Spider {
         Spider {
-            link_vector
+            link_vector,
+            max_depth: read_max_depth(),
+            scope: read_scope(),
+            max_pages: read_max_pages(),
+            workers: read_workers(),
+            visited: HashSet::new(),
+            pages: Vec::new(),
+            final_urls: HashMap::new(),
+            failures: Vec::new(),
         }
     }
 
-    pub fn dispatch(&mut self) -> Vec<String> {
+    pub fn dispatch(&mut self) -> Vec<PageWords> {
         println!("###  Spider dispatched");
+        if self.max_depth > 0 {
+            println!("###  Spider will crawl up to {} links deep with scope {:?}", self.max_depth, self.scope);
+        }
+
+        println!("###  Spider is now starting {} workers", self.workers);
+        // fetchers are queued on this channel and picked up by whichever worker is free
+        let (job_tx, job_rx) = mpsc::channel();
+        let workers = Spider::start_workers(self.workers, job_rx, Fetcher::dispatch);
 
         println!("###  Spider is now dispatching fetchers...");
-        //create workers and the master receiver
-        let (spider_rx, fetchers) = self.build_fetchers();
-        //start workers
-        for mut fetcher in fetchers {
-            thread::spawn(move || {
-                fetcher.dispatch();
-            });
+        // create a channel transmitter and receiver for interprocesses communication
+        let (tx, spider_rx) = mpsc::channel();
+        // clone the transmitter so it can be shared with the fetchers
+        let slave_tx = Arc::new(Mutex::new(tx));
+
+        // start a fetcher for every URL managed by the Spider, each one is the origin of its own crawl
+        let mut running: usize = 0;
+        for (link, engine) in self.link_vector.clone() {
+            if self.visited.insert(link.clone()) {
+                self.pages.push(link.clone());
+                // the pages at the deepest level don't need their links, so they aren't looked for
+                let fetcher = Fetcher::new(link.clone(), link.clone(), link, engine, 0, self.max_depth > 0, slave_tx.clone());
+                Spider::queue_fetcher(fetcher, &job_tx);
+                running += 1;
+            }
         }
 
         println!("###  Spider is now receiving results from the Fetchers");
@@ -39,44 +95,308 @@ impl Spider {
         }
         // create Vector to store the results
         let mut results = Vec::new();
-        // as long as it is open, read the contents of the receiver into the results Vector
-        for received in spider_rx {
-            results.push(received);
+        // read the receiver into the results Vector until every fetcher (including crawled ones) is finished
+        while running > 0 {
+            match spider_rx.recv() {
+                Ok(FetcherMessage::Words { url, seed, engine, words }) => results.push(PageWords { url, seed, engine, words }),
+                Ok(FetcherMessage::Links { origin, seed, engine, depth, links }) => {
+                    if depth > self.max_depth {
+                        continue;
+                    }
+                    for link in links {
+                        if self.in_scope(&origin, &link) && self.admit(&link) {
+                            if configuration::read_debug() {
+                                println!("~~~  Spider is crawling {} (depth {}, from {})", link, depth, origin);
+                            }
+                            let fetcher = Fetcher::new(link, origin.clone(), seed.clone(), engine.clone(), depth,
+                                                       depth < self.max_depth, slave_tx.clone());
+                            Spider::queue_fetcher(fetcher, &job_tx);
+                            running += 1;
+                        }
+                    }
+                }
+                Ok(FetcherMessage::Fetched { url, final_url }) => {
+                    self.final_urls.insert(url, final_url);
+                }
+                Ok(FetcherMessage::Failed { url, error }) => self.failures.push((url, error)),
+                Ok(FetcherMessage::Finished) => running -= 1,
+                Err(e) => {
+                    println!("!!!  Spider lost contact with its fetchers: {}", e);
+                    break;
+                }
+            }
+        }
+        println!("$$$  Spider has retrieved all values from workers after visiting {} pages", self.visited.len());
+
+        // closing the job queue lets the idle workers exit
+        drop(job_tx);
+        for worker in workers {
+            if worker.join().is_err() {
+                println!("!!!  A Spider worker panicked");
+            }
         }
-        println!("$$$  Spider has retrieved all values from workers");
 
         if configuration::read_debug() {
             println!("~~~  Spider is running in debug mode and will write everything to a special file");
             match File::create("debug_wordlist.txt") {
-                Ok(mut file) => for result in results.clone() {
-                    match write!(file, "{}\n", result) {
-                        Ok(_) => (),
-                        Err(e) => println!("!!!  Spider debug could not write special debug wordlist: {}", e)
+                Ok(mut file) => for page in &results {
+                    for result in &page.words {
+                        match writeln!(file, "{}\t{} {}{}\t{}", result.text, result.source.name(), result.kind.name(),
+                                       if result.critical { " (critical)" } else { "" }, page.url) {
+                            Ok(_) => (),
+                            Err(e) => println!("!!!  Spider debug could not write special debug wordlist: {}", e)
+                        }
                     }
                 },
                 Err(e) => println!("!!!  Spider debug could not create special debug wordlist: {}", e)
             }
-            
+
         }
 
         results
     }
 
-    fn build_fetchers(&mut self) -> (Receiver<String>, Vec<Fetcher>) {
-        // create list of fetchers so it can be managed
-        let mut fetchers = Vec::new();
-        // create a channel transmitter and receiver for interprocesses communication
-        let (tx, rx) = mpsc::channel();
-        // rereference the variable so it doesn't conflict
-        let master_rx = rx;
-        // clone the transmitter so it can be shared with the fetcher
+    // prints how many pages couldn't be fetched, grouped by why
+    pub fn report_failures(&self) {
+        if self.failures.is_empty() {
+            println!("$$$  Every page was fetched successfully");
+            return;
+        }
+        let mut by_kind: BTreeMap<String, usize> = BTreeMap::new();
+        for (_, error) in &self.failures {
+            *by_kind.entry(error.kind()).or_insert(0) += 1;
+        }
+        println!("!!!  {} of {} pages failed to be fetched:", self.failures.len(), self.visited.len());
+        for (kind, count) in by_kind {
+            println!("!!!    {} x {}", count, kind);
+        }
+        if configuration::read_debug() {
+            for (url, error) in &self.failures {
+                println!("F: {} ({})", url, error);
+            }
+        }
+    }
+
+    // each worker runs the job on every fetcher it takes off the queue
+    fn start_workers(count: usize, job_rx: Receiver<Fetcher>, job: fn(&mut Fetcher)) -> Vec<JoinHandle<()>> {
+        // the receiver is shared so each worker takes the next fetcher off the queue when it's free
+        let shared_rx = Arc::new(Mutex::new(job_rx));
+        (0..count).map(|_| {
+            let jobs = Arc::clone(&shared_rx);
+            thread::spawn(move || loop {
+                // only hold the lock while waiting for a job, not while running it
+                let next = match jobs.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => break
+                };
+                match next {
+                    Ok(mut fetcher) => {
+                        // a fetcher that panics never says it finished, and the Spider would wait for it forever
+                        // so the worker says it for the fetcher, and lives on to run the rest of the queue
+                        if panic::catch_unwind(AssertUnwindSafe(|| job(&mut fetcher))).is_err() {
+                            fetcher.abandon();
+                        }
+                    }
+                    Err(_) => break  // the Spider closed the queue
+                }
+            })
+        }).collect()
+    }
+
+    fn queue_fetcher(fetcher: Fetcher, jobs: &Sender<Fetcher>) {
+        if let Err(e) = jobs.send(fetcher) {
+            println!("!!!  Spider could not queue fetcher: {}", e);
+        }
+    }
+
+    // marks a crawled link as visited if it hasn't been seen yet and there's still room in the page budget
+    fn admit(&mut self, link: &str) -> bool {
+        if let Some(max) = self.max_pages {
+            if self.visited.len() >= max {
+                return false;
+            }
+        }
+        if self.visited.insert(link.to_string()) {
+            self.pages.push(link.to_string());
+            true
+        } else {
+            false
+        }
+    }
+
+    // every page the Spider tried to fetch, in order, along with where it ended up if it was fetched
+    pub fn fetched_links(&self) -> Vec<(String, Option<String>)> {
+        self.pages.iter()
+            .map(|link| (link.clone(), self.final_urls.get(link).cloned()))
+            .collect()
+    }
+
+    fn in_scope(&self, origin: &str, link: &str) -> bool {
+        if self.scope == CrawlScope::Any {
+            return true;
+        }
+        let (origin_url, link_url) = match (Url::parse(origin), Url::parse(link)) {
+            (Ok(origin_url), Ok(link_url)) => (origin_url, link_url),
+            _ => return false
+        };
+        if origin_url.host_str() != link_url.host_str() {
+            return false;
+        }
+        match self.scope {
+            CrawlScope::Path => {
+                // everything up to and including the last '/' is the directory the origin lives in
+                let origin_path = origin_url.path();
+                let prefix = &origin_path[..origin_path.rfind('/').map(|i| i + 1).unwrap_or(0)];
+                link_url.path().starts_with(prefix)
+            }
+            _ => true
+        }
+    }
+}
+
+fn read_max_depth() -> u32 {
+    match configuration::CONFIGURATION.read().unwrap().get_int("crawl.max_depth") {
+        Ok(value) if value >= 0 => value as u32,
+        Ok(_) => {
+            println!("!!!  \"crawl.max_depth\" can't be negative. Crawling is disabled");
+            0
+        }
+        Err(_) => 0
+    }
+}
+
+fn read_scope() -> CrawlScope {
+    match configuration::CONFIGURATION.read().unwrap().get_str("crawl.scope") {
+        Ok(value) => match value.to_lowercase().as_str() {
+            "domain" => CrawlScope::Domain,
+            "path" => CrawlScope::Path,
+            "any" => CrawlScope::Any,
+            other => {
+                println!("!!!  Unknown crawl scope \"{}\". Defaulting to \"domain\"", other);
+                CrawlScope::Domain
+            }
+        },
+        Err(_) => CrawlScope::Domain
+    }
+}
+
+fn read_max_pages() -> Option<usize> {
+    match configuration::CONFIGURATION.read().unwrap().get_int("crawl.max_pages") {
+        Ok(value) if value >= 0 => Some(value as usize),
+        _ => None
+    }
+}
+
+fn read_workers() -> usize {
+    match configuration::CONFIGURATION.read().unwrap().get_int("spider.workers") {
+        Ok(value) if value > 0 => value as usize,
+        Ok(_) => {
+            println!("!!!  \"spider.workers\" must be at least 1. Defaulting to 1");
+            1
+        }
+        Err(_) => {
+            println!("!!!  \"spider.workers\" key missing from configuration. Defaulting to 8");
+            8
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn spider(scope: CrawlScope, max_pages: Option<usize>) -> Spider {
+        Spider {
+            link_vector: Vec::new(),
+            max_depth: 1,
+            scope,
+            max_pages,
+            workers: 1,
+            visited: HashSet::new(),
+            pages: Vec::new(),
+            final_urls: HashMap::new(),
+            failures: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn domain_scope_stays_on_the_host() {
+        let spider = spider(CrawlScope::Domain, None);
+        let origin = "https://acme.example/products/rockets.html";
+        assert!(spider.in_scope(origin, "https://acme.example/about"));
+        assert!(spider.in_scope(origin, "http://acme.example/products/skates"));
+        assert!(!spider.in_scope(origin, "https://shop.acme.example/products/"));
+        assert!(!spider.in_scope(origin, "https://coyote.example/"));
+        assert!(!spider.in_scope(origin, "not a url"));
+    }
+
+    #[test]
+    fn path_scope_stays_under_the_origins_directory() {
+        let spider = spider(CrawlScope::Path, None);
+        let origin = "https://acme.example/products/rockets.html";
+        assert!(spider.in_scope(origin, "https://acme.example/products/skates"));
+        assert!(spider.in_scope(origin, "https://acme.example/products/anvils/heavy.html"));
+        assert!(!spider.in_scope(origin, "https://acme.example/about"));
+        assert!(!spider.in_scope(origin, "https://acme.example/productsale"));
+        assert!(!spider.in_scope(origin, "https://coyote.example/products/skates"));
+        // a directory is its own scope
+        assert!(spider.in_scope("https://acme.example/team/", "https://acme.example/team/wile"));
+    }
+
+    #[test]
+    fn any_scope_follows_everything() {
+        let spider = spider(CrawlScope::Any, None);
+        assert!(spider.in_scope("https://acme.example/products/", "https://coyote.example/"));
+    }
+
+    #[test]
+    fn admit_skips_repeats_and_stops_at_the_page_budget() {
+        let mut budgeted = spider(CrawlScope::Domain, Some(3));
+        assert!(budgeted.admit("https://acme.example/"));
+        assert!(!budgeted.admit("https://acme.example/"));
+        assert!(budgeted.admit("https://acme.example/about"));
+        assert!(budgeted.admit("https://acme.example/team"));
+        assert!(!budgeted.admit("https://acme.example/contact"));
+        assert_eq!(budgeted.pages, vec!["https://acme.example/", "https://acme.example/about", "https://acme.example/team"]);
+
+        let mut unlimited = spider(CrawlScope::Domain, None);
+        for page in 0..500 {
+            assert!(unlimited.admit(format!("https://acme.example/{}", page).as_str()));
+        }
+        let mut none = spider(CrawlScope::Domain, Some(0));
+        assert!(!none.admit("https://acme.example/"));
+    }
+
+    #[test]
+    fn panicking_fetchers_still_finish() {
+        let (job_tx, job_rx) = mpsc::channel();
+        let workers = Spider::start_workers(1, job_rx, |_| panic!("the fetcher crashed"));
+        let (tx, spider_rx) = mpsc::channel();
         let slave_tx = Arc::new(Mutex::new(tx));
-        // for every URL managed by the Spider, create a fetcher and give it a copy of the transmitter
-        for link in &self.link_vector {
-            fetchers.push(Fetcher::new(link.clone(),
-                                       slave_tx.clone())
-            );
+        for _ in 0..2 {
+            let url = "http://127.0.0.1/".to_string();
+            Spider::queue_fetcher(Fetcher::new(url.clone(), url.clone(), url, None, 0, false, slave_tx.clone()), &job_tx);
+        }
+
+        // both are reported even though the only worker's first fetcher panicked
+        let mut failed = 0;
+        let mut finished = 0;
+        while finished < 2 {
+            match spider_rx.recv_timeout(Duration::from_secs(10)).expect("the Spider would have waited forever") {
+                FetcherMessage::Failed { error, .. } => {
+                    assert_eq!(error, FetchError::Panic);
+                    failed += 1;
+                }
+                FetcherMessage::Finished => finished += 1,
+                _ => ()
+            }
+        }
+        assert_eq!(failed, 2);
+
+        drop(job_tx);
+        for worker in workers {
+            assert!(worker.join().is_ok());
         }
-        (master_rx, fetchers)
     }
-}
\ No newline at end of file
+}